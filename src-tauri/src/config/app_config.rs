@@ -0,0 +1,557 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    net::{load_certificates, parse_headers, parse_proxy, split_paths, DIRECT},
+    utils::{check_ffmpeg, from_spec, DownloadMode},
+};
+
+/// The config with its types, read with [`app_config`](super::app_config).
+/// The defaults here are the defaults of the config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    #[serde(rename = "user-agent")]
+    pub user_agent: String,
+    pub save_dir: PathBuf,
+    pub ffmpeg: String,
+    pub bili_cookie: String,
+    /// The cookie profile of tasks which do not choose one
+    pub bili_profile: String,
+    pub disk_reserve_mb: u64,
+    /// Parts are kept in `.part` of the save dir if empty
+    pub temp_dir: String,
+    pub filename_template: String,
+    pub filename_max_len: u64,
+    pub on_conflict: String,
+    pub quality: u32,
+    /// Bytes per second per task, 0 is unlimited
+    pub rate_limit: u64,
+    /// Comma separated post processors
+    pub post_process: String,
+    pub download_mode: DownloadMode,
+    /// Empty keeps audio only downloads as m4a
+    pub audio_format: String,
+    pub native_mux: bool,
+    /// Proxy of every request, none if empty
+    pub proxy: String,
+    /// Used instead of `proxy` for bilibili if set
+    pub bili_proxy: String,
+    /// Seconds
+    pub connect_timeout: u64,
+    /// Seconds a download may wait for data
+    pub read_timeout: u64,
+    /// PEM files of extra root certificates, separated by `;`
+    pub ca_certs: String,
+    pub http2: bool,
+    /// A json object of headers sent with every request
+    pub headers: String,
+    /// Over the built-in headers of bilibili, an empty value removes one
+    pub bili_headers: String,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: super::USER_AGENT.to_string(),
+            save_dir: dirs_next::download_dir().unwrap_or_default(),
+            ffmpeg: "ffmpeg".to_string(),
+            bili_cookie: String::new(),
+            bili_profile: super::profile::DEFAULT_PROFILE.to_string(),
+            disk_reserve_mb: 512,
+            temp_dir: String::new(),
+            filename_template: "{title}.{ext}".to_string(),
+            filename_max_len: 200,
+            on_conflict: "number".to_string(),
+            quality: 127,
+            rate_limit: 0,
+            post_process: String::new(),
+            download_mode: DownloadMode::Both,
+            audio_format: String::new(),
+            native_mux: true,
+            proxy: String::new(),
+            bili_proxy: String::new(),
+            connect_timeout: 10,
+            read_timeout: 30,
+            ca_certs: String::new(),
+            http2: true,
+            headers: String::new(),
+            bili_headers: String::new(),
+        }
+    }
+}
+
+/// [`AppConfig::default`] as the strings stored in config
+pub fn defaults() -> HashMap<String, String> {
+    let serde_json::Value::Object(map) = serde_json::to_value(AppConfig::default()).unwrap() else {
+        unreachable!("AppConfig is a struct")
+    };
+    map.into_iter()
+        .map(|(k, v)| match v {
+            serde_json::Value::String(s) => (k, s),
+            v => (k, v.to_string()),
+        })
+        .collect()
+}
+
+/// How a value is edited and checked
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FieldKind {
+    Text,
+    Dir,
+    /// A name in PATH or a full path, it must run
+    Program,
+    /// `{field}` placeholders, `/` makes sub directories
+    Template,
+    /// `name=value` pairs separated by `;`
+    Cookie,
+    PostProcess,
+    /// The name of a cookie profile
+    Profile,
+    /// `scheme://host:port`
+    Proxy,
+    /// PEM files separated by `;`
+    Certificates,
+    /// A json object of strings
+    Headers,
+    Number {
+        min: u64,
+        max: u64,
+    },
+    Bool,
+    Choice {
+        options: &'static [&'static str],
+    },
+}
+
+/// One config key, for the UI to render a form from
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldSchema {
+    pub key: &'static str,
+    pub description: &'static str,
+    #[serde(flatten)]
+    pub kind: FieldKind,
+    /// Also stored per cookie profile as `{key}_{profile}`
+    pub profiled: bool,
+    pub default: String,
+}
+
+/// A rejected value, `key` is empty if it is not about one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub key: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.key, self.message)
+    }
+}
+
+const FIELDS: &[(&str, &str, FieldKind, bool)] = &[
+    (
+        "user-agent",
+        "User agent of every request",
+        FieldKind::Text,
+        false,
+    ),
+    (
+        "save_dir",
+        "Where downloads are saved",
+        FieldKind::Dir,
+        false,
+    ),
+    (
+        "ffmpeg",
+        "ffmpeg, a full path on macOS",
+        FieldKind::Program,
+        false,
+    ),
+    ("bili_cookie", "Cookie of bilibili", FieldKind::Cookie, true),
+    (
+        "bili_profile",
+        "Cookie profile of bilibili tasks which do not choose one",
+        FieldKind::Profile,
+        false,
+    ),
+    (
+        "disk_reserve_mb",
+        "Free space kept on disk, in MiB",
+        FieldKind::Number {
+            min: 0,
+            max: 1 << 20,
+        },
+        false,
+    ),
+    (
+        "temp_dir",
+        "Where parts are downloaded, `.part` in the save dir if empty",
+        FieldKind::Dir,
+        false,
+    ),
+    (
+        "filename_template",
+        "Output path, e.g. {uploader}/{date} - {title}.{ext}",
+        FieldKind::Template,
+        false,
+    ),
+    (
+        "filename_max_len",
+        "Longest file or dir name, in bytes",
+        FieldKind::Number { min: 16, max: 255 },
+        false,
+    ),
+    (
+        "on_conflict",
+        "When the output exists: overwrite, skip or add a number",
+        FieldKind::Choice {
+            options: &["overwrite", "skip", "number"],
+        },
+        false,
+    ),
+    (
+        "quality",
+        "Preferred quality id, 127 is 8K, 80 is 1080P",
+        FieldKind::Choice {
+            options: &[
+                "127", "126", "125", "120", "116", "112", "80", "74", "64", "32", "16",
+            ],
+        },
+        false,
+    ),
+    (
+        "rate_limit",
+        "Bytes per second per task, 0 is unlimited",
+        FieldKind::Number {
+            min: 0,
+            max: 10_000_000_000,
+        },
+        false,
+    ),
+    (
+        "post_process",
+        "Comma separated steps after merging, e.g. mkv,loudnorm",
+        FieldKind::PostProcess,
+        false,
+    ),
+    (
+        "download_mode",
+        "Which streams are downloaded",
+        FieldKind::Choice {
+            options: &["both", "audio_only", "video_only"],
+        },
+        false,
+    ),
+    (
+        "audio_format",
+        "Format of audio only downloads, m4a if empty",
+        FieldKind::Choice {
+            options: &["", "mp3", "m4a", "flac"],
+        },
+        false,
+    ),
+    (
+        "native_mux",
+        "Merge bilibili streams without ffmpeg",
+        FieldKind::Bool,
+        false,
+    ),
+    (
+        "proxy",
+        "Proxy of every request, e.g. socks5://127.0.0.1:1080",
+        FieldKind::Proxy,
+        false,
+    ),
+    (
+        "bili_proxy",
+        "Proxy of bilibili instead of proxy, direct for none",
+        FieldKind::Proxy,
+        false,
+    ),
+    (
+        "connect_timeout",
+        "Seconds to wait for a connection",
+        FieldKind::Number { min: 1, max: 300 },
+        false,
+    ),
+    (
+        "read_timeout",
+        "Seconds a download waits for data before asking again",
+        FieldKind::Number { min: 1, max: 3600 },
+        false,
+    ),
+    (
+        "ca_certs",
+        "Extra root certificates, PEM files separated by ;",
+        FieldKind::Certificates,
+        false,
+    ),
+    (
+        "http2",
+        "Use HTTP/2 where the server offers it",
+        FieldKind::Bool,
+        false,
+    ),
+    (
+        "headers",
+        r#"Headers of every request, e.g. {"Accept-Language": "zh-CN"}"#,
+        FieldKind::Headers,
+        false,
+    ),
+    (
+        "bili_headers",
+        r#"Headers of bilibili over the built-in Referer and Origin, e.g. {"User-Agent": "..."}, "" removes one"#,
+        FieldKind::Headers,
+        false,
+    ),
+];
+
+pub fn schema() -> Vec<FieldSchema> {
+    let mut defaults = defaults();
+    FIELDS
+        .iter()
+        .map(|(key, description, kind, profiled)| FieldSchema {
+            key,
+            description,
+            kind: kind.clone(),
+            profiled: *profiled,
+            default: defaults.remove(*key).unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// The field of `key`, which is the field itself or a profile of it
+fn field_of(key: &str) -> Option<&'static (&'static str, &'static str, FieldKind, bool)> {
+    FIELDS.iter().find(|(field, _, _, profiled)| {
+        key == *field
+            || *profiled
+                && key
+                    .strip_prefix(field)
+                    .and_then(|k| k.strip_prefix('_'))
+                    .is_some_and(|profile| !profile.is_empty())
+    })
+}
+
+/// `{name}_proxy` and `{name}_headers` of an extractor without fields of its own,
+/// e.g. `generic_headers` or those of a script
+fn site_kind(key: &str) -> Option<FieldKind> {
+    let (site, kind) = key
+        .strip_suffix("_proxy")
+        .map(|site| (site, FieldKind::Proxy))
+        .or_else(|| {
+            key.strip_suffix("_headers")
+                .map(|site| (site, FieldKind::Headers))
+        })?;
+    crate::task::list_extractors()
+        .iter()
+        .any(|e| e.name == site)
+        .then_some(kind)
+}
+
+/// Whether `key` holds credentials, left out of bundles shared with others
+pub fn is_secret(key: &str) -> bool {
+    matches!(field_of(key), Some((_, _, FieldKind::Cookie, _)))
+}
+
+/// Check the values about to be saved. Unchanged values, compared with `current`,
+/// are not checked again, and empty ones reset to the default.
+pub fn validate<F>(kv: &HashMap<String, String>, current: F) -> Result<(), Vec<FieldError>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut errors = kv
+        .iter()
+        .filter(|(key, value)| !value.is_empty() && current(key).as_ref() != Some(value))
+        .filter_map(|(key, value)| {
+            let kind = field_of(key)
+                .map(|(_, _, kind, _)| kind.clone())
+                .or_else(|| site_kind(key));
+            let message = match kind {
+                Some(kind) => validate_field(&kind, value).err()?,
+                None => "is not a known config".to_string(),
+            };
+            Some(FieldError {
+                key: key.clone(),
+                message,
+            })
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    errors.sort_by(|a, b| a.key.cmp(&b.key));
+    Err(errors)
+}
+
+fn validate_field(kind: &FieldKind, value: &str) -> Result<(), String> {
+    match kind {
+        FieldKind::Text => Ok(()),
+        FieldKind::Dir => match std::path::Path::new(value).is_dir() {
+            true => Ok(()),
+            false => Err("is not a directory".to_string()),
+        },
+        FieldKind::Program => check_ffmpeg(value)
+            .map(|_| ())
+            .map_err(|e| format!("does not work: {e}")),
+        FieldKind::Template => {
+            if value.matches('{').count() != value.matches('}').count() {
+                return Err("has unbalanced braces".to_string());
+            }
+            Ok(())
+        }
+        FieldKind::Cookie => {
+            let valid = value
+                .split(';')
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty())
+                .all(|pair| {
+                    pair.split_once('=').is_some_and(|(name, _)| {
+                        !name.is_empty() && !name.contains(char::is_whitespace)
+                    })
+                });
+            match valid {
+                true => Ok(()),
+                false => Err("is not like name=value; name=value".to_string()),
+            }
+        }
+        FieldKind::PostProcess => value
+            .split(',')
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+            .find(|spec| from_spec(spec).is_none())
+            .map_or(Ok(()), |spec| Err(format!("has an unknown step {spec}"))),
+        FieldKind::Profile => match super::profile::valid_name(value) {
+            true => Ok(()),
+            false => Err("may only have letters, digits, - and _".to_string()),
+        },
+        FieldKind::Proxy if value == DIRECT => Ok(()),
+        FieldKind::Proxy => parse_proxy(value).map(|_| ()).map_err(|e| e.to_string()),
+        FieldKind::Certificates => load_certificates(&split_paths(value))
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        FieldKind::Headers => parse_headers(value).map(|_| ()).map_err(|e| e.to_string()),
+        FieldKind::Number { min, max } => match value.parse::<u64>() {
+            Ok(n) if (*min..=*max).contains(&n) => Ok(()),
+            Ok(_) => Err(format!("must be between {min} and {max}")),
+            Err(_) => Err("is not a number".to_string()),
+        },
+        FieldKind::Bool => match value {
+            "true" | "false" => Ok(()),
+            _ => Err("must be true or false".to_string()),
+        },
+        FieldKind::Choice { options } => match options.contains(&value) {
+            true => Ok(()),
+            false => Err(format!("must be one of {}", options.join(", "))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deserialize_test() {
+        let mut builder = config::Config::builder();
+        for (key, value) in defaults() {
+            builder = builder.set_default(key, value).unwrap();
+        }
+        let config = builder
+            .set_override("rate_limit", "1024")
+            .unwrap()
+            .set_override("download_mode", "audio_only")
+            .unwrap()
+            .build()
+            .unwrap();
+        let app_config: AppConfig = config.try_deserialize().unwrap();
+        assert_eq!(app_config.rate_limit, 1024);
+        assert_eq!(app_config.download_mode, DownloadMode::AudioOnly);
+        assert_eq!(app_config.quality, 127);
+        assert!(app_config.native_mux);
+        assert_eq!(app_config.user_agent, super::super::USER_AGENT);
+    }
+
+    #[test]
+    fn schema_test() {
+        let schema = schema();
+        assert_eq!(schema.len(), defaults().len());
+        let quality = schema.iter().find(|f| f.key == "quality").unwrap();
+        assert_eq!(quality.default, "127");
+        let json = serde_json::to_value(quality).unwrap();
+        assert_eq!(json["type"], "choice");
+    }
+
+    #[test]
+    fn validate_test() {
+        let current = |key: &str| (key == "hello").then(|| "world".to_string());
+        let kv = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        assert!(validate(
+            &kv(&[
+                ("hello", "world"),
+                ("save_dir", ""),
+                ("quality", "80"),
+                ("rate_limit", "1024"),
+                ("bili_cookie", "SESSDATA=abc; bili_jct=def"),
+                ("bili_cookie_work", "SESSDATA=abc"),
+                ("post_process", "mkv, loudnorm"),
+                ("filename_template", "{uploader}/{title}.{ext}"),
+                ("proxy", "socks5h://127.0.0.1:1080"),
+                ("bili_proxy", "direct"),
+                ("headers", r#"{"DNT": "1"}"#),
+                ("bili_headers", r#"{"Origin": ""}"#),
+                ("generic_headers", r#"{"Referer": ""}"#),
+                ("generic_proxy", "direct"),
+            ]),
+            current
+        )
+        .is_ok());
+        let errors = validate(
+            &kv(&[
+                ("unknown", "1"),
+                ("save_dir", "/not/exist"),
+                ("ffmpeg", "/not/exist/ffmpeg"),
+                ("filename_max_len", "1000"),
+                ("native_mux", "yes"),
+                ("bili_cookie", "not a cookie"),
+                ("bili_cookie_", "SESSDATA=abc"),
+                ("post_process", "mkv,unknown"),
+                ("filename_template", "{title.{ext}"),
+                ("proxy", "127.0.0.1:1080"),
+                ("ca_certs", "/not/exist.pem"),
+                ("headers", "DNT: 1"),
+                ("generic_proxy", "127.0.0.1:1080"),
+                ("nosuch_headers", "{}"),
+            ]),
+            current,
+        )
+        .unwrap_err();
+        let keys = errors.iter().map(|e| e.key.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "bili_cookie",
+                "bili_cookie_",
+                "ca_certs",
+                "ffmpeg",
+                "filename_max_len",
+                "filename_template",
+                "generic_proxy",
+                "headers",
+                "native_mux",
+                "nosuch_headers",
+                "post_process",
+                "proxy",
+                "save_dir",
+                "unknown",
+            ]
+        );
+    }
+}
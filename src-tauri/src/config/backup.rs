@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use snafu::ensure;
+
+use super::{
+    app_config::{is_secret, validate, FieldError},
+    encrypt::Encrypter,
+    error::{config_error, ConfigResult},
+    key_provider::derive_key,
+    KeySource,
+};
+
+/// Start of every bundle
+const MAGIC: &[u8; 4] = b"DLCB";
+/// Bumped whenever the layout after the magic changes
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN;
+/// Authenticated with the values, see [`Encrypter`]
+const BUNDLE_NAME: &str = "bundle";
+
+/// Every value set in config, the cookies only if `include_secrets`,
+/// encrypted with a key derived from `passphrase` so it opens on any machine
+pub fn export(passphrase: &str, include_secrets: bool) -> ConfigResult<Vec<u8>> {
+    let values = KeySource::new()?
+        .inner
+        .into_iter()
+        .map(|(k, v)| (k, v.to_string()))
+        .filter(|(k, _)| include_secrets || !is_secret(k))
+        .collect();
+    seal(&values, passphrase)
+}
+
+/// Save the values of `bundle` encrypted by the local key provider.
+/// Values which are invalid here, e.g. a save dir which does not exist, are skipped and returned.
+pub fn import(bundle: &[u8], passphrase: &str) -> ConfigResult<Vec<FieldError>> {
+    let (valid, skipped) = split_valid(open(bundle, passphrase)?);
+    KeySource::new()?.upgrade(valid)?;
+    crate::net::invalidate();
+    Ok(skipped)
+}
+
+fn split_valid(values: HashMap<String, String>) -> (HashMap<String, String>, Vec<FieldError>) {
+    let mut skipped = vec![];
+    let valid = values
+        .into_iter()
+        .filter(
+            |(k, v)| match validate(&HashMap::from([(k.clone(), v.clone())]), |_| None) {
+                Ok(()) => true,
+                Err(errors) => {
+                    skipped.extend(errors);
+                    false
+                }
+            },
+        )
+        .collect();
+    skipped.sort_by(|a, b| a.key.cmp(&b.key));
+    (valid, skipped)
+}
+
+fn seal(values: &HashMap<String, String>, passphrase: &str) -> ConfigResult<Vec<u8>> {
+    ensure!(!passphrase.is_empty(), config_error::EmptyPassphrase);
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let encrypter = Encrypter::with_key(derive_key(passphrase, &salt)?);
+    let mut bundle = MAGIC.to_vec();
+    bundle.push(VERSION);
+    bundle.extend_from_slice(&salt);
+    bundle.extend(encrypter.encrypt(BUNDLE_NAME, values)?);
+    Ok(bundle)
+}
+
+fn open(bundle: &[u8], passphrase: &str) -> ConfigResult<HashMap<String, String>> {
+    ensure!(
+        bundle.len() > HEADER_LEN && bundle.starts_with(MAGIC),
+        config_error::BadBundle
+    );
+    let version = bundle[MAGIC.len()];
+    ensure!(
+        version == VERSION,
+        config_error::UnknownVersionError {
+            name: BUNDLE_NAME,
+            version
+        }
+    );
+    let salt = &bundle[MAGIC.len() + 1..HEADER_LEN];
+    let encrypter = Encrypter::with_key(derive_key(passphrase, salt)?);
+    encrypter
+        .decrypt(BUNDLE_NAME, &bundle[HEADER_LEN..])
+        .map_err(|_| config_error::WrongPassphrase.build())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values() -> HashMap<String, String> {
+        HashMap::from([
+            ("quality".to_string(), "80".to_string()),
+            ("bili_cookie".to_string(), "SESSDATA=abc".to_string()),
+        ])
+    }
+
+    #[test]
+    fn seal_test() {
+        let bundle = seal(&values(), "secret").unwrap();
+        assert!(bundle.starts_with(MAGIC));
+        assert_eq!(open(&bundle, "secret").unwrap(), values());
+        assert!(matches!(
+            open(&bundle, "wrong"),
+            Err(crate::config::error::ConfigError::WrongPassphrase)
+        ));
+        assert!(matches!(
+            open(b"not a bundle", "secret"),
+            Err(crate::config::error::ConfigError::BadBundle)
+        ));
+        assert!(seal(&values(), "").is_err());
+    }
+
+    #[test]
+    fn split_valid_test() {
+        let mut values = values();
+        values.insert("save_dir".to_string(), "/not/exist".to_string());
+        let (valid, skipped) = split_valid(values);
+        assert_eq!(valid.len(), 2);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].key, "save_dir");
+        assert!(is_secret("bili_cookie") && is_secret("bili_cookie_work"));
+        assert!(!is_secret("quality"));
+    }
+}
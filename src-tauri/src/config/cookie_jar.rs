@@ -0,0 +1,247 @@
+use reqwest::header::HeaderValue;
+use snafu::OptionExt;
+use url::Url;
+
+use super::{
+    error::{config_error, ConfigResult},
+    get_config,
+    profile::{cookie_key, domain_of},
+    save_profile, DEFAULT_PROFILE,
+};
+
+/// The cookies of one profile of a site.
+/// They stay a cookie header in config, read on every request,
+/// so a login or an edit in the config page is used at once.
+pub struct CookieJar {
+    site: String,
+    domain: &'static str,
+    profile: String,
+}
+
+impl CookieJar {
+    pub fn new(site: &str, profile: Option<&str>) -> ConfigResult<Self> {
+        let domain = domain_of(site).context(config_error::UnknownSiteError { site })?;
+        Ok(Self {
+            site: site.to_string(),
+            domain,
+            profile: profile
+                .filter(|p| !p.is_empty())
+                .unwrap_or(DEFAULT_PROFILE)
+                .to_string(),
+        })
+    }
+
+    pub fn header(&self) -> String {
+        get_config(cookie_key(&self.site, Some(&self.profile))).unwrap_or_default()
+    }
+
+    fn save(&self, header: &str) -> ConfigResult<()> {
+        save_profile(&self.site, &self.profile, header)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        url.host_str()
+            .is_some_and(|host| in_domain(host, self.domain))
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        if !self.matches(url) {
+            return;
+        }
+        let updates = cookie_headers
+            .filter_map(|h| h.to_str().ok())
+            .filter_map(SetCookie::parse)
+            .filter(|c| {
+                c.domain
+                    .as_deref()
+                    .is_none_or(|d| in_domain(d, self.domain))
+            })
+            .collect::<Vec<_>>();
+        if updates.is_empty() {
+            return;
+        }
+        let header = self.header();
+        let merged = merge(&header, updates);
+        if merged != header {
+            if let Err(e) = self.save(&merged) {
+                tracing::warn!("cookies of {} are not saved: {}", self.site, e);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        if !self.matches(url) {
+            return None;
+        }
+        Some(self.header())
+            .filter(|h| !h.is_empty())
+            .and_then(|h| HeaderValue::from_str(&h).ok())
+    }
+}
+
+/// Merge the cookies of a Netscape `cookies.txt` into a profile of `site`,
+/// those of other domains and the expired ones are skipped. Returns how many were merged.
+pub fn import_cookies_txt(site: &str, profile: Option<&str>, text: &str) -> ConfigResult<usize> {
+    let jar = CookieJar::new(site, profile)?;
+    let updates = parse_cookies_txt(text, jar.domain, chrono::Utc::now().timestamp());
+    let count = updates.len();
+    jar.save(&merge(&jar.header(), updates))?;
+    Ok(count)
+}
+
+/// A profile of `site` as a Netscape `cookies.txt`.
+/// The header keeps no attributes, so all are session cookies of the whole domain.
+pub fn export_cookies_txt(site: &str, profile: Option<&str>) -> ConfigResult<String> {
+    let jar = CookieJar::new(site, profile)?;
+    let mut text = "# Netscape HTTP Cookie File\n".to_string();
+    for (name, value) in pairs(&jar.header()) {
+        text += &format!(".{}\tTRUE\t/\tFALSE\t0\t{name}\t{value}\n", jar.domain);
+    }
+    Ok(text)
+}
+
+#[derive(Debug, PartialEq)]
+struct SetCookie {
+    name: String,
+    value: String,
+    domain: Option<String>,
+    /// Set to a past date or an empty value to delete it
+    removed: bool,
+}
+
+impl SetCookie {
+    fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: None,
+            removed: value.is_empty(),
+        };
+        for (key, value) in parts.filter_map(|p| p.split_once('=')) {
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" => cookie.domain = Some(value.to_string()),
+                "max-age" => cookie.removed |= value.parse::<i64>().is_ok_and(|age| age <= 0),
+                "expires" => {
+                    cookie.removed |= chrono::DateTime::parse_from_rfc2822(value)
+                        .is_ok_and(|date| date < chrono::Utc::now())
+                }
+                _ => (),
+            }
+        }
+        Some(cookie)
+    }
+}
+
+fn in_domain(host: &str, domain: &str) -> bool {
+    let host = host.trim_start_matches('.');
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+fn pairs(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// Apply `updates` to a cookie header, keeping the order of the cookies it has
+fn merge(header: &str, updates: Vec<SetCookie>) -> String {
+    let mut cookies = pairs(header);
+    for update in updates {
+        let pos = cookies.iter().position(|(k, _)| *k == update.name);
+        match (pos, update.removed) {
+            (Some(i), true) => {
+                cookies.remove(i);
+            }
+            (Some(i), false) => cookies[i].1 = update.value,
+            (None, true) => (),
+            (None, false) => cookies.push((update.name, update.value)),
+        }
+    }
+    cookies
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The cookies of `domain` in a Netscape `cookies.txt` which have not expired at `now`
+fn parse_cookies_txt(text: &str, domain: &str, now: i64) -> Vec<SetCookie> {
+    text.lines()
+        .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line))
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields = line.trim_end_matches('\r').split('\t').collect::<Vec<_>>();
+            let [host, _, _, _, expires, name, value] = fields[..] else {
+                return None;
+            };
+            let expires = expires.parse::<i64>().unwrap_or(0);
+            (in_domain(host, domain) && (expires == 0 || expires > now)).then(|| SetCookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain: Some(host.to_string()),
+                removed: false,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_cookie_test() {
+        let cookie =
+            SetCookie::parse("SESSDATA=abc%2C1; Path=/; Domain=.bilibili.com; HttpOnly").unwrap();
+        assert_eq!(cookie.name, "SESSDATA");
+        assert_eq!(cookie.value, "abc%2C1");
+        assert_eq!(cookie.domain.as_deref(), Some(".bilibili.com"));
+        assert!(!cookie.removed);
+        assert!(SetCookie::parse("bili_jct=; Max-Age=0").unwrap().removed);
+        assert!(
+            SetCookie::parse("sid=x; Expires=Thu, 01 Jan 1970 00:00:00 GMT")
+                .unwrap()
+                .removed
+        );
+        assert!(SetCookie::parse("HttpOnly").is_none());
+    }
+
+    #[test]
+    fn merge_test() {
+        let updates = ["SESSDATA=new", "bili_jct=; Max-Age=0", "buvid3=b"]
+            .into_iter()
+            .filter_map(SetCookie::parse)
+            .collect();
+        assert_eq!(
+            merge("SESSDATA=old; bili_jct=j; DedeUserID=42", updates),
+            "SESSDATA=new; DedeUserID=42; buvid3=b"
+        );
+        assert!(in_domain("api.bilibili.com", "bilibili.com"));
+        assert!(in_domain(".bilibili.com", "bilibili.com"));
+        assert!(!in_domain("notbilibili.com", "bilibili.com"));
+    }
+
+    #[test]
+    fn cookies_txt_test() {
+        let text = "# Netscape HTTP Cookie File\n\
+            .bilibili.com\tTRUE\t/\tFALSE\t0\tSESSDATA\tabc\n\
+            #HttpOnly_.bilibili.com\tTRUE\t/\tTRUE\t2000000000\tbili_jct\tj\n\
+            .bilibili.com\tTRUE\t/\tFALSE\t1000\tsid\texpired\n\
+            .example.com\tTRUE\t/\tFALSE\t0\tother\tx\n\
+            malformed line\n";
+        let cookies = parse_cookies_txt(text, "bilibili.com", 1_700_000_000);
+        assert_eq!(merge("", cookies), "SESSDATA=abc; bili_jct=j");
+    }
+}
@@ -1,66 +1,148 @@
-use super::error::ConfigResult;
-use rsa::{Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use super::error::{config_error, ConfigResult};
+use super::key_provider::{keyring_entry, KeyProvider};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rsa::{traits::PublicKeyParts, Pkcs1v15Encrypt, RsaPrivateKey};
+use snafu::{ensure, OptionExt};
 
-#[derive(serde::Serialize, serde::Deserialize)]
+/// Start of every encrypted config file
+const MAGIC: &[u8; 4] = b"DLCF";
+/// Bumped whenever the layout after the magic changes
+const VERSION: u8 = 1;
+/// Written by [`PlaintextProvider`](super::key_provider::PlaintextProvider), json follows the magic
+const PLAIN_VERSION: u8 = 0;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + NONCE_LEN;
+
+/// Encrypts config values with XChaCha20-Poly1305 under the data key of a [`KeyProvider`].
+/// A file is [`MAGIC`], [`VERSION`], a random nonce and the ciphertext.
+/// The header and the config key are authenticated as well,
+/// so a file can not be renamed to another key unnoticed.
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct Encrypter {
-    priv_key: RsaPrivateKey,
+    key: Option<Key>,
 }
 
 impl Encrypter {
+    #[cfg(test)]
     fn new() -> Self {
-        let mut rng = rand::thread_rng();
-        let bits = if cfg!(not(target_os = "windows")) {
-            2048
-        } else {
-            1024 // too long isn't accepted by Win
-        };
-        let priv_key = RsaPrivateKey::new(&mut rng, bits).expect("failed to generate a key");
-        Self { priv_key }
+        Self {
+            key: Some(XChaCha20Poly1305::generate_key(&mut OsRng)),
+        }
     }
 
-    pub fn from_key_ring() -> ConfigResult<Self> {
-        let entry = keyring_entry();
-        match entry.get_password() {
-            Ok(serded_enc) => Ok(serde_json::from_str(&serded_enc).unwrap()),
-            Err(_) => {
-                let new_enc = Encrypter::new();
-                entry.set_password(&serde_json::to_string(&new_enc).unwrap())?;
-                Ok(new_enc)
-            }
-        }
+    pub fn with_key(key: Key) -> Self {
+        Self { key: Some(key) }
     }
 
-    pub fn encrypt<I>(&self, origin: &I) -> ConfigResult<Vec<u8>>
+    pub fn from_provider(provider: &dyn KeyProvider) -> ConfigResult<Self> {
+        Ok(Self {
+            key: provider.data_key()?,
+        })
+    }
+
+    /// Whether `data` is in the current format, not necessarily a version this build reads
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.len() > MAGIC.len() && data.starts_with(MAGIC)
+    }
+
+    pub fn encrypt<I>(&self, name: &str, origin: &I) -> ConfigResult<Vec<u8>>
     where
         I: serde::Serialize,
     {
-        let mut rng = rand::thread_rng();
-        let origin = serde_json::to_vec(origin).unwrap();
-        let chunk_size = if cfg!(not(target_os = "windows")) {
-            245 // (2048 >> 3) - 11
-        } else {
-            117 // (1024 >> 3) - 11
+        let mut encrypted = MAGIC.to_vec();
+        let Some(key) = &self.key else {
+            encrypted.push(PLAIN_VERSION);
+            encrypted.extend(serde_json::to_vec(origin)?);
+            return Ok(encrypted);
         };
-        let pub_key = RsaPublicKey::from(&self.priv_key);
-        let mut encrypted = vec![];
-        for c in origin.chunks(chunk_size) {
-            encrypted.extend(pub_key.encrypt(&mut rng, Pkcs1v15Encrypt, c)?);
-        }
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        encrypted.push(VERSION);
+        encrypted.extend_from_slice(&nonce);
+        let payload = Payload {
+            msg: &serde_json::to_vec(origin)?,
+            aad: &aad(&encrypted, name),
+        };
+        let ciphertext = XChaCha20Poly1305::new(key)
+            .encrypt(&nonce, payload)
+            .ok()
+            .context(config_error::CipherError { name })?;
+        encrypted.extend(ciphertext);
         Ok(encrypted)
     }
 
+    pub fn decrypt<R>(&self, name: &str, encrypted: &[u8]) -> ConfigResult<R>
+    where
+        for<'de> R: serde::Deserialize<'de>,
+    {
+        ensure!(
+            Self::is_encrypted(encrypted),
+            config_error::CipherError { name }
+        );
+        let version = encrypted[MAGIC.len()];
+        if version == PLAIN_VERSION {
+            // unauthenticated, so never trusted once a key is used
+            ensure!(self.key.is_none(), config_error::CipherError { name });
+            return Ok(serde_json::from_slice(&encrypted[MAGIC.len() + 1..])?);
+        }
+        ensure!(
+            version == VERSION,
+            config_error::UnknownVersionError { name, version }
+        );
+        let key = self
+            .key
+            .as_ref()
+            .context(config_error::CipherError { name })?;
+        ensure!(
+            encrypted.len() >= HEADER_LEN,
+            config_error::CipherError { name }
+        );
+        let (header, ciphertext) = encrypted.split_at(HEADER_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad(header, name),
+        };
+        let decrypted = XChaCha20Poly1305::new(key)
+            .decrypt(XNonce::from_slice(&header[MAGIC.len() + 1..]), payload)
+            .ok()
+            .context(config_error::CipherError { name })?;
+        let origin = serde_json::from_slice(&decrypted)?;
+        Ok(origin)
+    }
+}
+
+fn aad(header: &[u8], name: &str) -> Vec<u8> {
+    [header, name.as_bytes()].concat()
+}
+
+/// The RSA key which encrypted the config in chunks before [`Encrypter`].
+/// Only read to migrate those files, it is removed from the keyring afterwards.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct LegacyEncrypter {
+    priv_key: RsaPrivateKey,
+}
+
+impl LegacyEncrypter {
+    /// The old key, if it is still in the keyring
+    pub fn from_key_ring() -> Option<Self> {
+        let serded_enc = keyring_entry("downloader").get_password().ok()?;
+        serde_json::from_str(&serded_enc).ok()
+    }
+
+    pub fn remove_from_key_ring() -> ConfigResult<()> {
+        keyring_entry("downloader").delete_password()?;
+        Ok(())
+    }
+
     pub fn decrypt<R>(&self, encrypted: &[u8]) -> ConfigResult<R>
     where
         for<'de> R: serde::Deserialize<'de>,
     {
         let mut decrypted = vec![];
-        let chunk_size = if cfg!(not(target_os = "windows")) {
-            256
-        } else {
-            128
-        };
-        for c in encrypted.chunks(chunk_size) {
+        // one PKCS#1 v1.5 block per chunk, 1024 bits keys were used on Windows
+        for c in encrypted.chunks(self.priv_key.size()) {
             decrypted.extend(self.priv_key.decrypt(Pkcs1v15Encrypt, c)?);
         }
         let origin = serde_json::from_slice(&decrypted)?;
@@ -68,11 +150,6 @@ impl Encrypter {
     }
 }
 
-fn keyring_entry() -> keyring::Entry {
-    let user = std::env::var("USER").unwrap_or("downloader user".to_string());
-    keyring::Entry::new_with_target("user", "downloader", &user).unwrap()
-}
-
 #[cfg(test)]
 mod test {
     use rand::distributions::{Alphanumeric, DistString};
@@ -84,40 +161,86 @@ mod test {
     fn encrypt_test() {
         let encrypter = Encrypter::new();
         let orgin = HashSet::from([("1".to_string(), "1".to_string())]);
-        let encrypted = encrypter.encrypt(&orgin);
+        let encrypted = encrypter.encrypt("key", &orgin);
         assert!(encrypted.is_ok());
         let encrypted = encrypted.unwrap();
-        let decrypted = encrypter.decrypt::<HashSet<(String, String)>>(&encrypted);
+        assert!(Encrypter::is_encrypted(&encrypted));
+        let decrypted = encrypter.decrypt::<HashSet<(String, String)>>("key", &encrypted);
         assert!(decrypted.is_ok());
         assert_eq!(decrypted.unwrap(), orgin);
     }
 
     #[test]
     fn encrtpter_create_test() {
-        let e1 = Encrypter::from_key_ring().unwrap();
-        let e2 = Encrypter::from_key_ring().unwrap();
+        let provider = crate::config::key_provider::KeyringProvider;
+        let e1 = Encrypter::from_provider(&provider).unwrap();
+        let e2 = Encrypter::from_provider(&provider).unwrap();
         assert_eq!(e1, e2);
         let data = HashMap::from([(
             Alphanumeric.sample_string(&mut rand::thread_rng(), 1024),
             Alphanumeric.sample_string(&mut rand::thread_rng(), 1024),
         )]);
-        let enc_ret1 = e1.encrypt(&data).unwrap();
-        let enc_ret2 = e2.encrypt(&data).unwrap();
+        let enc_ret1 = e1.encrypt("key", &data).unwrap();
+        let enc_ret2 = e2.encrypt("key", &data).unwrap();
         assert_ne!(enc_ret1, enc_ret2);
         assert_eq!(
-            e1.decrypt::<HashMap<String, String>>(&enc_ret1).unwrap(),
-            e2.decrypt::<HashMap<String, String>>(&enc_ret2).unwrap()
+            e1.decrypt::<HashMap<String, String>>("key", &enc_ret1)
+                .unwrap(),
+            e2.decrypt::<HashMap<String, String>>("key", &enc_ret2)
+                .unwrap()
         );
     }
 
     #[test]
-    fn encrypter_string_test() {
-        let s = "hello world".to_string();
+    fn tamper_test() {
         let encrypter = Encrypter::new();
-        let enc_ret = encrypter.encrypt(&s);
-        assert!(enc_ret.is_ok());
-        let dec_ret = encrypter.decrypt::<String>(&enc_ret.unwrap());
-        assert!(dec_ret.is_ok());
-        assert_eq!(s, dec_ret.unwrap());
+        let mut encrypted = encrypter.encrypt("key", &"hello world").unwrap();
+        assert!(encrypter.decrypt::<String>("other", &encrypted).is_err());
+        assert!(Encrypter::new()
+            .decrypt::<String>("key", &encrypted)
+            .is_err());
+        encrypted[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            encrypter.decrypt::<String>("key", &encrypted),
+            Err(crate::config::error::ConfigError::UnknownVersion { .. })
+        ));
+        encrypted[MAGIC.len()] = VERSION;
+        *encrypted.last_mut().unwrap() ^= 1;
+        assert!(encrypter.decrypt::<String>("key", &encrypted).is_err());
+    }
+
+    #[test]
+    fn legacy_test() {
+        let mut rng = rand::thread_rng();
+        let legacy = LegacyEncrypter {
+            priv_key: RsaPrivateKey::new(&mut rng, 1024).unwrap(),
+        };
+        let pub_key = rsa::RsaPublicKey::from(&legacy.priv_key);
+        let s = Alphanumeric.sample_string(&mut rng, 300);
+        // how the old files were written
+        let mut encrypted = vec![];
+        for c in serde_json::to_vec(&s).unwrap().chunks(128 - 11) {
+            encrypted.extend(pub_key.encrypt(&mut rng, Pkcs1v15Encrypt, c).unwrap());
+        }
+        assert!(!Encrypter::is_encrypted(&encrypted));
+        assert_eq!(legacy.decrypt::<String>(&encrypted).unwrap(), s);
+    }
+
+    #[test]
+    fn plaintext_test() {
+        let plain = Encrypter { key: None };
+        let written = plain.encrypt("key", &"hello world").unwrap();
+        assert!(Encrypter::is_encrypted(&written));
+        assert_eq!(
+            plain.decrypt::<String>("key", &written).unwrap(),
+            "hello world"
+        );
+        // anyone able to write the config dir could plant it
+        assert!(matches!(
+            Encrypter::new().decrypt::<String>("key", &written),
+            Err(crate::config::error::ConfigError::Cipher { .. })
+        ));
+        let encrypted = Encrypter::new().encrypt("key", &"hello world").unwrap();
+        assert!(plain.decrypt::<String>("key", &encrypted).is_err());
     }
 }
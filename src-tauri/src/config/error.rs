@@ -1,18 +1,67 @@
 use snafu::Snafu;
 
+use super::FieldError;
+
 #[derive(Debug, Snafu)]
 #[snafu(module, visibility(pub(crate)), context(suffix(Error)))]
 pub enum ConfigError {
-    #[snafu(context(false))]
+    #[snafu(
+        display(
+            "Could not access the keyring: {}, set {} to passphrase or plaintext if there is none",
+            source,
+            super::PROVIDER_ENV
+        ),
+        context(false)
+    )]
     NotAccessible { source: keyring::error::Error },
     #[snafu(context(false))]
     SourceError { source: config::ConfigError },
     #[snafu(context(false))]
     EncrypterError { source: rsa::errors::Error },
+    #[snafu(display("Could not encrypt or decrypt config {}", name))]
+    Cipher { name: String },
+    #[snafu(display("Config {} is written by a newer version {}", name, version))]
+    UnknownVersion { name: String, version: u8 },
+    #[snafu(
+        display("The config key in the keyring is malformed"),
+        context(suffix(false))
+    )]
+    BadDataKey,
+    #[snafu(context(false))]
+    IoError { source: std::io::Error },
+    #[snafu(display("Unknown key provider {}, use keyring, passphrase or plaintext", spec))]
+    UnknownKeyProvider { spec: String },
+    #[snafu(
+        display("Set the passphrase in {}", super::PASSPHRASE_ENV),
+        context(suffix(false))
+    )]
+    PassphraseMissing,
+    #[snafu(display("Could not derive the config key: {}", message))]
+    KeyDerivation { message: String },
+    #[snafu(
+        display("The key provider is chosen before the config is read"),
+        context(suffix(false))
+    )]
+    KeyProviderSet,
+    #[snafu(display("The passphrase can not be empty"), context(suffix(false)))]
+    EmptyPassphrase,
+    #[snafu(display("Not a config bundle"), context(suffix(false)))]
+    BadBundle,
+    #[snafu(
+        display("Wrong passphrase, or the bundle is damaged"),
+        context(suffix(false))
+    )]
+    WrongPassphrase,
+    #[snafu(display("{} has no cookie profiles", site))]
+    UnknownSite { site: String },
+    #[snafu(display("Profile name {} may only have letters, digits, - and _", name))]
+    BadProfileName { name: String },
     #[snafu(context(false))]
     WrongEncrypterSerde { source: serde_json::Error },
     #[snafu(context(suffix(false)))]
     ConfigDirUnknown,
+    #[snafu(display("Invalid config: {}", errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")))]
+    Invalid { errors: Vec<FieldError> },
 }
 
 pub type ConfigResult<T> = Result<T, ConfigError>;
@@ -0,0 +1,227 @@
+use std::sync::OnceLock;
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, KeyInit, OsRng},
+    Key, XChaCha20Poly1305,
+};
+use snafu::OptionExt;
+
+use super::error::{config_error, ConfigResult};
+
+/// Selects the provider when `--key-provider` is not given
+pub const PROVIDER_ENV: &str = "DOWNLOADER_KEY_PROVIDER";
+/// The passphrase of [`PassphraseProvider`], never taken from the command line
+pub const PASSPHRASE_ENV: &str = "DOWNLOADER_PASSPHRASE";
+/// Stored next to the config, skipped when loading it
+pub const SALT_FILE: &str = ".salt";
+
+static KEY_PROVIDER: OnceLock<Box<dyn KeyProvider>> = OnceLock::new();
+
+/// Where the data key encrypting the config comes from
+pub trait KeyProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// `None` stores the config as plain text
+    fn data_key(&self) -> ConfigResult<Option<Key>>;
+}
+
+/// Parse `keyring`, `passphrase` or `plaintext`
+pub fn from_spec(spec: &str) -> ConfigResult<Box<dyn KeyProvider>> {
+    match spec {
+        "keyring" => Ok(Box::new(KeyringProvider)),
+        "passphrase" => {
+            let passphrase = std::env::var(PASSPHRASE_ENV)
+                .ok()
+                .filter(|p| !p.is_empty())
+                .context(config_error::PassphraseMissing)?;
+            Ok(Box::new(PassphraseProvider::new(passphrase)))
+        }
+        "plaintext" => Ok(Box::new(PlaintextProvider)),
+        _ => config_error::UnknownKeyProviderError { spec }.fail(),
+    }
+}
+
+/// Use `spec` for this run, fails if the config has been read already
+pub fn set_key_provider(spec: &str) -> ConfigResult<()> {
+    let provider = from_spec(spec)?;
+    warn_plaintext(provider.as_ref());
+    KEY_PROVIDER
+        .set(provider)
+        .ok()
+        .context(config_error::KeyProviderSet)
+}
+
+/// The provider set by [`set_key_provider`], or from [`PROVIDER_ENV`], or the keyring
+pub fn key_provider() -> &'static dyn KeyProvider {
+    KEY_PROVIDER
+        .get_or_init(|| {
+            let spec = std::env::var(PROVIDER_ENV).unwrap_or("keyring".to_string());
+            let provider = from_spec(&spec).unwrap_or_else(|e| {
+                tracing::error!("{}, falling back to the keyring", e);
+                Box::new(KeyringProvider)
+            });
+            warn_plaintext(provider.as_ref());
+            provider
+        })
+        .as_ref()
+}
+
+fn warn_plaintext(provider: &dyn KeyProvider) {
+    if provider.name() == "plaintext" {
+        tracing::warn!("config and cookies are stored unencrypted");
+    }
+}
+
+/// A random key kept in the system's key keeper
+pub struct KeyringProvider;
+
+impl KeyProvider for KeyringProvider {
+    fn name(&self) -> &'static str {
+        "keyring"
+    }
+
+    /// Created only if there is none yet
+    fn data_key(&self) -> ConfigResult<Option<Key>> {
+        let entry = keyring_entry("downloader-data-key");
+        match entry.get_password() {
+            Ok(hex) => Ok(Some(
+                from_hex(&hex)
+                    .filter(|k| k.len() == 32)
+                    .map(|k| *Key::from_slice(&k))
+                    .context(config_error::BadDataKey)?,
+            )),
+            Err(keyring::Error::NoEntry) => {
+                let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+                entry.set_password(&to_hex(&key))?;
+                Ok(Some(key))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// A key derived from a passphrase with Argon2id, for systems without a key keeper
+pub struct PassphraseProvider {
+    passphrase: String,
+    // derivation is slow on purpose, and the config is read often
+    key: OnceLock<Key>,
+}
+
+impl PassphraseProvider {
+    pub fn new(passphrase: String) -> Self {
+        Self {
+            passphrase,
+            key: OnceLock::new(),
+        }
+    }
+
+    /// The salt of this config dir, created on first use
+    fn salt() -> ConfigResult<Vec<u8>> {
+        let path = super::config_dir()?.join(SALT_FILE);
+        match std::fs::read(&path) {
+            Ok(salt) => Ok(salt),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut salt = vec![0; 16];
+                OsRng.fill_bytes(&mut salt);
+                std::fs::write(&path, &salt)?;
+                Ok(salt)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl KeyProvider for PassphraseProvider {
+    fn name(&self) -> &'static str {
+        "passphrase"
+    }
+
+    fn data_key(&self) -> ConfigResult<Option<Key>> {
+        if let Some(key) = self.key.get() {
+            return Ok(Some(*key));
+        }
+        let key = derive_key(&self.passphrase, &Self::salt()?)?;
+        Ok(Some(*self.key.get_or_init(|| key)))
+    }
+}
+
+/// No encryption at all, chosen explicitly and warned about
+pub struct PlaintextProvider;
+
+impl KeyProvider for PlaintextProvider {
+    fn name(&self) -> &'static str {
+        "plaintext"
+    }
+
+    fn data_key(&self) -> ConfigResult<Option<Key>> {
+        Ok(None)
+    }
+}
+
+/// Derive a key from `passphrase` with Argon2id
+pub(super) fn derive_key(passphrase: &str, salt: &[u8]) -> ConfigResult<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| {
+            config_error::KeyDerivationError {
+                message: e.to_string(),
+            }
+            .build()
+        })?;
+    Ok(key)
+}
+
+pub(super) fn keyring_entry(service: &str) -> keyring::Entry {
+    let user = std::env::var("USER").unwrap_or("downloader user".to_string());
+    keyring::Entry::new_with_target("user", service, &user).unwrap()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derive_test() {
+        let salt = b"0123456789abcdef";
+        let key = derive_key("hello", salt).unwrap();
+        assert_eq!(key, derive_key("hello", salt).unwrap());
+        assert_ne!(key, derive_key("world", salt).unwrap());
+        assert_ne!(key, derive_key("hello", b"fedcba9876543210").unwrap());
+    }
+
+    #[test]
+    fn from_spec_test() {
+        assert_eq!(from_spec("plaintext").unwrap().name(), "plaintext");
+        assert!(from_spec("plaintext")
+            .unwrap()
+            .data_key()
+            .unwrap()
+            .is_none());
+        assert_eq!(from_spec("keyring").unwrap().name(), "keyring");
+        assert!(matches!(
+            from_spec("unknown"),
+            Err(crate::config::error::ConfigError::UnknownKeyProvider { .. })
+        ));
+    }
+
+    #[test]
+    fn hex_test() {
+        let bytes = [0u8, 1, 0xab, 0xff];
+        assert_eq!(to_hex(&bytes), "0001abff");
+        assert_eq!(from_hex("0001abff").unwrap(), bytes);
+        assert!(from_hex("0g").is_none());
+        assert!(from_hex("abc").is_none());
+    }
+}
@@ -1,32 +1,38 @@
+mod app_config;
+mod backup;
+mod cookie_jar;
 mod encrypt;
 pub mod error;
+mod key_provider;
+mod profile;
 
 use config::{Config, Source, Value, ValueKind};
 use error::ConfigResult;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::{collections::HashSet, sync::OnceLock};
 
 use error::config_error;
 use snafu::OptionExt;
 
+pub use app_config::{schema, AppConfig, FieldError, FieldSchema};
+pub use backup::{export as export_config, import as import_config};
+pub use cookie_jar::{export_cookies_txt, import_cookies_txt, CookieJar};
+pub use key_provider::{set_key_provider, PASSPHRASE_ENV, PROVIDER_ENV};
+pub use profile::{cookie_key, profiles, save_profile, DEFAULT_PROFILE};
+
 static mut APP_CONFIG: OnceLock<Config> = OnceLock::new();
 const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Safari/605.1.15";
 
 pub fn config_init() -> ConfigResult<()> {
     unsafe {
         APP_CONFIG.take();
-        let config = Config::builder()
-            .set_default("user-agent", USER_AGENT)?
-            .set_default(
-                "save_dir",
-                dirs_next::download_dir()
-                    .context(config_error::ConfigDirUnknown)?
-                    .to_str(),
-            )?
-            .set_default("ffmpeg", "ffmpeg")?
-            .set_default("bili_cookie", "")?
-            .add_source(KeySource::new()?)
-            .build()?;
+        dirs_next::download_dir().context(config_error::ConfigDirUnknown)?;
+        let mut builder = Config::builder();
+        for (key, value) in app_config::defaults() {
+            builder = builder.set_default(key, value)?;
+        }
+        let config = builder.add_source(KeySource::new()?).build()?;
         APP_CONFIG.set(config).unwrap();
     }
     Ok(())
@@ -37,12 +43,34 @@ pub fn get_config<S: AsRef<str>>(key: S) -> Option<String> {
     unsafe { APP_CONFIG.get()?.get_string(key.as_ref()).ok() }
 }
 
+/// The whole config with its types
+pub fn app_config() -> ConfigResult<AppConfig> {
+    config_init()?;
+    unsafe {
+        let config = APP_CONFIG.get().context(config_error::ConfigDirUnknown)?;
+        Ok(config.clone().try_deserialize()?)
+    }
+}
+
+/// Validate and save `kv`, nothing is saved if a value is rejected
 pub fn upgrade_config<KV>(kv: KV) -> ConfigResult<()>
 where
     KV: Into<KeySource>,
 {
+    let key_source: KeySource = kv.into();
+    let kv = key_source
+        .inner
+        .iter()
+        .map(|(k, v)| (k.clone(), v.to_string()))
+        .collect();
+    app_config::validate(&kv, |key| get_config(key))
+        .map_err(|errors| config_error::InvalidError { errors }.build())?;
     let mut keysource = KeySource::new()?;
-    keysource.upgrade(kv)?;
+    keysource.upgrade(key_source)?;
+    // cookies are read by the jar on each request, saving them keeps the clients
+    if kv.keys().any(|key| !app_config::is_secret(key)) {
+        crate::net::invalidate();
+    }
     Ok(())
 }
 
@@ -83,28 +111,59 @@ impl KeySource {
     }
 
     fn new() -> ConfigResult<Self> {
-        let config_dir = dirs_next::config_dir()
-            .context(config_error::ConfigDirUnknown)?
-            .join("downloader");
-        std::fs::create_dir_all(&config_dir).unwrap();
+        let config_dir = config_dir()?;
+        let encrypter = encrypt::Encrypter::from_provider(key_provider::key_provider())?;
+        let legacy = encrypt::LegacyEncrypter::from_key_ring();
+        let (values, unreadable) = Self::read(&config_dir, &encrypter, legacy.as_ref())?;
+        if legacy.is_some() && !unreadable {
+            encrypt::LegacyEncrypter::remove_from_key_ring()?;
+        }
+        Ok(values.into())
+    }
+
+    /// The values stored in `config_dir`, and whether any of them could not be read.
+    /// Files written by the legacy key are stored again with `encrypter`
+    fn read(
+        config_dir: &Path,
+        encrypter: &encrypt::Encrypter,
+        legacy: Option<&encrypt::LegacyEncrypter>,
+    ) -> ConfigResult<(HashMap<String, String>, bool)> {
         let mut ret = HashMap::new();
-        let encrypter = encrypt::Encrypter::from_key_ring()?;
-        let entry = std::fs::read_dir(&config_dir).unwrap();
-        for path in entry.filter_map(|p| p.map(|p| p.path()).ok()) {
-            match encrypter.decrypt::<String>(&std::fs::read(&path).unwrap()) {
+        let mut unreadable = false;
+        for entry in std::fs::read_dir(config_dir)?.filter_map(|e| e.ok()) {
+            let filename = entry.file_name().to_string_lossy().to_string();
+            if filename.ends_with("_new") || filename.starts_with('.') {
+                // left by an interrupted save, or not a config value
+                continue;
+            }
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let encrypted = std::fs::read(&path)?;
+            let data = if encrypt::Encrypter::is_encrypted(&encrypted) {
+                encrypter.decrypt::<String>(&filename, &encrypted)
+            } else if let Some(legacy) = legacy {
+                legacy.decrypt::<String>(&encrypted).and_then(|data| {
+                    let encrypted = encrypter.encrypt(&filename, &data)?;
+                    write_atomic(config_dir, &filename, &encrypted)?;
+                    Ok(data)
+                })
+            } else {
+                config_error::CipherError { name: &filename }.fail()
+            };
+            match data {
                 Ok(data) => {
-                    ret.insert(
-                        path.file_name().unwrap().to_string_lossy().to_string(),
-                        data,
-                    );
+                    ret.insert(filename, data);
                 }
-                Err(_) => {
-                    // can not decrypt, so delete the file
-                    std::fs::remove_file(&path).unwrap();
+                Err(e) => {
+                    // kept, it may be readable with the right key
+                    tracing::warn!("config {} is not loaded: {}", filename, e);
+                    unreadable = true;
                 }
             }
         }
-        Ok(ret.into())
+        Ok((ret, unreadable))
     }
 
     fn upgrade<KV>(&mut self, kv: KV) -> ConfigResult<()>
@@ -118,28 +177,48 @@ impl KeySource {
     }
 
     fn save(&self) -> ConfigResult<()> {
-        let config_dir = dirs_next::config_dir()
-            .context(config_error::ConfigDirUnknown)?
-            .join("downloader");
-        std::fs::create_dir_all(&config_dir).unwrap();
-        let encrypter = encrypt::Encrypter::from_key_ring()?;
+        let config_dir = config_dir()?;
+        let encrypter = encrypt::Encrypter::from_provider(key_provider::key_provider())?;
         for (filename, data) in self.inner.iter() {
             if data.to_string().is_empty() {
                 std::fs::remove_file(config_dir.join(filename)).ok();
                 continue;
             }
-            let encrypted = encrypter.encrypt(&data.to_string()).unwrap();
-            std::fs::write(config_dir.join(format!("{filename}_new")), &encrypted).unwrap();
-            std::fs::rename(
-                config_dir.join(format!("{filename}_new")),
-                config_dir.join(filename),
-            )
-            .unwrap();
+            let encrypted = encrypter.encrypt(filename, &data.to_string())?;
+            write_atomic(&config_dir, filename, &encrypted)?;
         }
         Ok(())
     }
 }
 
+fn config_dir() -> ConfigResult<PathBuf> {
+    let config_dir = dirs_next::config_dir()
+        .context(config_error::ConfigDirUnknown)?
+        .join("downloader");
+    std::fs::create_dir_all(&config_dir)?;
+    Ok(config_dir)
+}
+
+/// Where the app keeps what is not a config value, e.g. extractor scripts.
+/// Every file of [`config_dir`] is read as one, and on macOS the data dir of the
+/// system is its config dir as well, so the name differs
+pub fn data_dir() -> ConfigResult<PathBuf> {
+    let data_dir = dirs_next::data_dir()
+        .context(config_error::ConfigDirUnknown)?
+        .join("downloader-data");
+    std::fs::create_dir_all(&data_dir)?;
+    Ok(data_dir)
+}
+
+fn write_atomic(config_dir: &Path, filename: &str, data: &[u8]) -> ConfigResult<()> {
+    std::fs::write(config_dir.join(format!("{filename}_new")), data)?;
+    std::fs::rename(
+        config_dir.join(format!("{filename}_new")),
+        config_dir.join(filename),
+    )?;
+    Ok(())
+}
+
 impl Source for KeySource {
     fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
         Box::new(self.to_owned())
@@ -206,6 +285,29 @@ mod test {
         assert_eq!(keysource.inner["hello"].to_string(), USER_AGENT);
     }
 
+    #[test]
+    fn read_test() {
+        let dir = tempdir::TempDir::new("config").unwrap();
+        let encrypter = encrypt::Encrypter::with_key(Default::default());
+        let encrypted = encrypter.encrypt("quality", &"80").unwrap();
+        std::fs::write(dir.path().join("quality"), encrypted).unwrap();
+        // e.g. a folder of scripts put here by hand
+        std::fs::create_dir(dir.path().join("extractors")).unwrap();
+        std::fs::write(dir.path().join("quality_new"), "partial").unwrap();
+        let (values, unreadable) = KeySource::read(dir.path(), &encrypter, None).unwrap();
+        assert_eq!(
+            values,
+            HashMap::from([("quality".to_string(), "80".to_string())])
+        );
+        assert!(!unreadable);
+    }
+
+    #[test]
+    fn upgrade_invalid_test() {
+        let ret = upgrade_config(HashMap::from([("ffmpeg", "/not/exist/ffmpeg")]));
+        assert!(matches!(ret, Err(error::ConfigError::Invalid { .. })));
+    }
+
     #[test]
     fn show_config_test() {
         let hm = show_config().unwrap();
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use snafu::ensure;
+
+use super::{
+    error::{config_error, ConfigResult},
+    upgrade_config, KeySource,
+};
+
+/// Sites with cookie profiles and the domain of their cookies,
+/// the cookies are `{site}_cookie` in config
+pub const SITES: &[(&str, &str)] = &[("bili", "bilibili.com")];
+/// Stored as `{site}_cookie`, used unless a task or `{site}_profile` chooses another
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The config key of the cookie of `site` for `profile`,
+/// `{site}_cookie` for the default profile and `{site}_cookie_{profile}` for the others
+pub fn cookie_key(site: &str, profile: Option<&str>) -> String {
+    match profile {
+        None | Some("") | Some(DEFAULT_PROFILE) => format!("{site}_cookie"),
+        Some(profile) => format!("{site}_cookie_{profile}"),
+    }
+}
+
+/// The domain the cookies of `site` are sent to, `None` if it has no profiles
+pub fn domain_of(site: &str) -> Option<&'static str> {
+    SITES.iter().find(|(s, _)| *s == site).map(|(_, d)| *d)
+}
+
+/// Letters, digits, `-` and `_`, up to 32 of them
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The profiles of `site` with a cookie stored, the default one first
+pub fn profiles(site: &str) -> ConfigResult<Vec<String>> {
+    ensure!(
+        domain_of(site).is_some(),
+        config_error::UnknownSiteError { site }
+    );
+    let keys = KeySource::new()?
+        .inner
+        .into_iter()
+        .filter(|(_, v)| !v.to_string().is_empty())
+        .map(|(k, _)| k);
+    Ok(profiles_in(site, keys))
+}
+
+fn profiles_in<I: Iterator<Item = String>>(site: &str, keys: I) -> Vec<String> {
+    let prefix = cookie_key(site, None) + "_";
+    let mut named = keys
+        .filter_map(|k| k.strip_prefix(&prefix).map(str::to_string))
+        .filter(|name| valid_name(name) && name != DEFAULT_PROFILE)
+        .collect::<Vec<_>>();
+    named.sort();
+    [vec![DEFAULT_PROFILE.to_string()], named].concat()
+}
+
+/// Store the cookie of a profile, an empty cookie removes it
+pub fn save_profile(site: &str, name: &str, cookie: &str) -> ConfigResult<()> {
+    ensure!(
+        domain_of(site).is_some(),
+        config_error::UnknownSiteError { site }
+    );
+    ensure!(valid_name(name), config_error::BadProfileNameError { name });
+    upgrade_config(HashMap::from([(cookie_key(site, Some(name)), cookie)]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cookie_key_test() {
+        assert_eq!(cookie_key("bili", None), "bili_cookie");
+        assert_eq!(cookie_key("bili", Some("default")), "bili_cookie");
+        assert_eq!(cookie_key("bili", Some("premium")), "bili_cookie_premium");
+        assert!(valid_name("premium-4k_1"));
+        assert!(!valid_name("") && !valid_name("a b") && !valid_name("../a"));
+    }
+
+    #[test]
+    fn profiles_test() {
+        let keys = [
+            "bili_cookie",
+            "bili_cookie_work",
+            "bili_cookie_premium",
+            "user-agent",
+        ]
+        .map(str::to_string);
+        assert_eq!(
+            profiles_in("bili", keys.into_iter()),
+            ["default", "premium", "work"]
+        );
+        assert!(matches!(
+            save_profile("bili", "a b", "SESSDATA=abc"),
+            Err(crate::config::error::ConfigError::BadProfileName { .. })
+        ));
+        assert!(profiles("unknown").is_err());
+    }
+}
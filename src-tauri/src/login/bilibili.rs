@@ -0,0 +1,188 @@
+use snafu::{ensure, OptionExt};
+
+use super::error::{login_error, LoginResult};
+use crate::config::{save_profile, DEFAULT_PROFILE};
+
+const GENERATE_API: &str = "https://passport.bilibili.com/x/passport-login/web/qrcode/generate";
+const POLL_API: &str = "https://passport.bilibili.com/x/passport-login/web/qrcode/poll";
+/// The cookies kept from a login, the others only track the session
+const KEPT_COOKIES: &[&str] = &[
+    "SESSDATA",
+    "bili_jct",
+    "DedeUserID",
+    "DedeUserID__ckMd5",
+    "sid",
+];
+
+/// A QR code to scan with the bilibili app
+#[derive(Debug, serde::Serialize)]
+pub struct QrCode {
+    /// The payload to render as a QR code
+    pub url: String,
+    /// Passed to [`poll`], valid for 180 seconds
+    pub key: String,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum QrStatus {
+    Waiting,
+    /// Scanned, but not confirmed in the app yet
+    Scanned,
+    /// Generate a new one
+    Expired,
+    /// The cookies are saved in `profile`
+    Done {
+        profile: String,
+    },
+}
+
+#[derive(serde::Deserialize)]
+struct Response<T> {
+    code: i64,
+    #[serde(default)]
+    message: String,
+    data: Option<T>,
+}
+
+#[derive(serde::Deserialize)]
+struct Generated {
+    url: String,
+    qrcode_key: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Polled {
+    code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    url: String,
+}
+
+/// Without a cookie jar, so the cookies of the login are read from the response
+fn client() -> LoginResult<reqwest::Client> {
+    Ok(crate::net::client(Some("bili"))?)
+}
+
+fn data<T>(resp: Response<T>) -> LoginResult<T> {
+    let Response {
+        code,
+        message,
+        data,
+    } = resp;
+    ensure!(code == 0, login_error::ApiError { code, message });
+    data.context(login_error::ApiError { code, message })
+}
+
+/// Ask for a new QR code
+pub async fn generate() -> LoginResult<QrCode> {
+    let resp = client()?.get(GENERATE_API).send().await?;
+    let generated = data(resp.json::<Response<Generated>>().await?)?;
+    Ok(QrCode {
+        url: generated.url,
+        key: generated.qrcode_key,
+    })
+}
+
+/// Check the QR code once, the cookies are saved into `profile` once it is confirmed
+pub async fn poll(key: &str, profile: Option<&str>) -> LoginResult<QrStatus> {
+    let resp = client()?
+        .get(POLL_API)
+        .query(&[("qrcode_key", key)])
+        .send()
+        .await?;
+    let set_cookies = resp
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok().map(str::to_string))
+        .collect::<Vec<_>>();
+    let polled = data(resp.json::<Response<Polled>>().await?)?;
+    match polled.code {
+        0 => {
+            let cookie = cookie_of(set_cookies.iter().map(String::as_str), &polled.url)
+                .context(login_error::CookieMissing)?;
+            let profile = profile.unwrap_or(DEFAULT_PROFILE);
+            save_profile("bili", profile, &cookie)?;
+            Ok(QrStatus::Done {
+                profile: profile.to_string(),
+            })
+        }
+        86101 => Ok(QrStatus::Waiting),
+        86090 => Ok(QrStatus::Scanned),
+        86038 => Ok(QrStatus::Expired),
+        code => login_error::ApiError {
+            code,
+            message: polled.message,
+        }
+        .fail(),
+    }
+}
+
+/// The cookie header of a login, from its `Set-Cookie` headers,
+/// or from the query of the cross domain url if there are none.
+/// Values are kept url encoded, as bilibili sends them.
+fn cookie_of<'a, I>(set_cookies: I, url: &str) -> Option<String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut pairs = set_cookies
+        .filter_map(|c| c.split(';').next()?.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect::<Vec<_>>();
+    if pairs.is_empty() {
+        let query = url.split_once('?').map(|(_, q)| q).unwrap_or_default();
+        pairs = query.split('&').filter_map(|p| p.split_once('=')).collect();
+    }
+    let cookie = KEPT_COOKIES
+        .iter()
+        .filter_map(|name| pairs.iter().find(|(k, _)| k == name))
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>();
+    cookie
+        .iter()
+        .any(|c| c.starts_with("SESSDATA="))
+        .then(|| cookie.join("; "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cookie_of_test() {
+        let set_cookies = [
+            "SESSDATA=abc%2C1700000000%2Cdef*11; Path=/; Domain=bilibili.com; HttpOnly; Secure",
+            "bili_jct=0123456789abcdef; Path=/; Domain=bilibili.com",
+            "DedeUserID=42; Path=/; Domain=bilibili.com",
+            "buvid3=unrelated; Path=/",
+        ];
+        assert_eq!(
+            cookie_of(set_cookies.into_iter(), "").unwrap(),
+            "SESSDATA=abc%2C1700000000%2Cdef*11; bili_jct=0123456789abcdef; DedeUserID=42"
+        );
+        let url = "https://passport.biligame.com/x/passport-login/web/crossDomain?DedeUserID=42&SESSDATA=abc%2C1700000000%2Cdef*11&bili_jct=0123&gourl=https%3A%2F%2Fwww.bilibili.com";
+        assert_eq!(
+            cookie_of(std::iter::empty(), url).unwrap(),
+            "SESSDATA=abc%2C1700000000%2Cdef*11; bili_jct=0123; DedeUserID=42"
+        );
+        assert!(cookie_of(["buvid3=unrelated"].into_iter(), url).is_none());
+    }
+
+    #[test]
+    fn data_test() {
+        let resp = serde_json::from_str::<Response<Polled>>(
+            r#"{"code":0,"message":"0","data":{"url":"","refresh_token":"","timestamp":0,"code":86101,"message":"未扫码"}}"#,
+        )
+        .unwrap();
+        assert_eq!(data(resp).unwrap().code, 86101);
+        let resp =
+            serde_json::from_str::<Response<Polled>>(r#"{"code":-412,"message":"请求被拦截"}"#)
+                .unwrap();
+        assert!(matches!(
+            data(resp),
+            Err(crate::login::error::LoginError::Api { code: -412, .. })
+        ));
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{config::error::ConfigError, net::error::NetError};
+use snafu::prelude::*;
+
+#[derive(Debug, Snafu)]
+#[snafu(module, visibility(pub(crate)), context(suffix(Error)))]
+pub enum LoginError {
+    #[snafu(display("Maybe network disconnected"), context(false))]
+    Network { source: reqwest::Error },
+    #[snafu(display("Login failed with {}: {}", code, message))]
+    Api { code: i64, message: String },
+    #[snafu(
+        display("Logged in, but no cookie was returned"),
+        context(suffix(false))
+    )]
+    CookieMissing,
+    #[snafu(display("{}", source), context(false))]
+    Config { source: ConfigError },
+    #[snafu(display("{}", source), context(false))]
+    Net { source: NetError },
+}
+
+pub type LoginResult<T> = Result<T, LoginError>;
@@ -0,0 +1,4 @@
+mod bilibili;
+pub mod error;
+
+pub use bilibili::{generate as bili_qrcode, poll as bili_poll, QrCode, QrStatus};
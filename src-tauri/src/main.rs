@@ -1,6 +1,5 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-#![feature(type_alias_impl_trait)]
 
 use model::TaskBmc;
 use std::{
@@ -8,9 +7,12 @@ use std::{
     collections::HashMap,
     sync::{Mutex, OnceLock},
 };
+use task::TaskOptions;
 
 mod config;
+mod login;
 mod model;
+mod net;
 mod task;
 mod tracing_helper;
 mod utils;
@@ -22,11 +24,15 @@ fn echo(s: &str) -> &str {
     s
 }
 
+/// The id of the new task, or why no extractor could take the url
 #[tauri::command]
-fn create(url: String) -> String {
+fn create(url: String, options: Option<TaskOptions>) -> Result<String, String> {
     let task_bmc = TASK_BMC.get().unwrap().lock().unwrap();
-    let uuid = task_bmc.borrow_mut().create(url).unwrap();
-    uuid.to_string()
+    let uuid = task_bmc
+        .borrow_mut()
+        .create(url, options.unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+    Ok(uuid.to_string())
 }
 
 macro_rules! gen_tauri_task_handler {
@@ -51,20 +57,160 @@ fn show_config() -> HashMap<String, String> {
 }
 
 #[tauri::command]
-fn upgrade_config(json: HashMap<String, String>) {
-    crate::config::upgrade_config(json).ok();
+fn upgrade_config(json: HashMap<String, String>) -> Result<(), Vec<config::FieldError>> {
+    crate::config::upgrade_config(json).map_err(|e| match e {
+        config::error::ConfigError::Invalid { errors } => errors,
+        e => vec![config::FieldError {
+            key: String::new(),
+            message: e.to_string(),
+        }],
+    })
 }
 
 #[tauri::command]
-fn progress() -> Vec<(String, usize, usize, String, String)> {
+fn export_config(path: String, passphrase: String, include_secrets: bool) -> Result<(), String> {
+    let bundle =
+        crate::config::export_config(&passphrase, include_secrets).map_err(|e| e.to_string())?;
+    std::fs::write(path, bundle).map_err(|e| e.to_string())
+}
+
+/// The values which were skipped for being invalid here
+#[tauri::command]
+fn import_config(path: String, passphrase: String) -> Result<Vec<config::FieldError>, String> {
+    let bundle = std::fs::read(path).map_err(|e| e.to_string())?;
+    crate::config::import_config(&bundle, &passphrase).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_extractors() -> Vec<task::ExtractorInfo> {
+    task::list_extractors()
+}
+
+/// Load the extractor scripts again, returns why some could not be loaded
+#[tauri::command]
+fn reload_extractors() -> Result<Vec<String>, String> {
+    task::load_scripts()
+        .map(|errors| errors.iter().map(|e| e.to_string()).collect())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_profiles(site: String) -> Result<Vec<String>, String> {
+    crate::config::profiles(&site).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn save_profile(site: String, name: String, cookie: String) -> Result<(), String> {
+    crate::config::save_profile(&site, &name, &cookie).map_err(|e| e.to_string())
+}
+
+/// Login, VIP and cookie expiry of a bilibili profile, the one in config if `None`
+#[tauri::command]
+async fn bili_account(profile: Option<String>) -> Result<task::Account, String> {
+    let profile = profile.or_else(|| crate::config::get_config("bili_profile"));
+    task::Account::of_profile(profile.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn import_cookies(site: String, profile: Option<String>, path: String) -> Result<usize, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    crate::config::import_cookies_txt(&site, profile.as_deref(), &text).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn export_cookies(site: String, profile: Option<String>, path: String) -> Result<(), String> {
+    let text =
+        crate::config::export_cookies_txt(&site, profile.as_deref()).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn bili_login_qrcode() -> Result<login::QrCode, String> {
+    login::bili_qrcode().await.map_err(|e| e.to_string())
+}
+
+/// Called every few seconds until the code is confirmed or expired
+#[tauri::command]
+async fn bili_login_poll(key: String, profile: Option<String>) -> Result<login::QrStatus, String> {
+    login::bili_poll(&key, profile.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn config_schema() -> Vec<config::FieldSchema> {
+    crate::config::schema()
+}
+
+#[tauri::command]
+fn diagnostics() -> crate::utils::Diagnostics {
+    crate::utils::diagnose()
+}
+
+#[tauri::command]
+fn progress() -> Vec<(String, usize, usize, String, String, String)> {
     let task_bmc = TASK_BMC.get().unwrap().lock().unwrap();
     let ret = task_bmc.borrow().progress().unwrap();
     ret
 }
 
+/// `--key-provider <keyring|passphrase|plaintext>` overrides the environment
+fn parse_args() {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let spec = match arg.strip_prefix("--key-provider") {
+            Some("") => args.next(),
+            Some(spec) => spec.strip_prefix('=').map(str::to_string),
+            None => None,
+        };
+        if let Some(spec) = spec {
+            if let Err(e) = crate::config::set_key_provider(&spec) {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+}
+
 fn main() {
     crate::tracing_helper::init_tracing_subscriber();
-    TASK_BMC.get_or_init(|| Mutex::new(RefCell::new(TaskBmc::new())));
+    parse_args();
+    if let Err(e) = task::load_scripts() {
+        tracing::warn!("could not load extractor scripts: {}", e);
+    }
+    let task_bmc = TASK_BMC.get_or_init(|| Mutex::new(RefCell::new(TaskBmc::new())));
+    // after the scripts, a restored task may need one of them
+    match task_bmc.lock().unwrap().borrow_mut().restore() {
+        Ok(pending) => {
+            let alive = pending
+                .tasks
+                .iter()
+                .filter_map(|t| t.uuid())
+                .collect::<Vec<_>>();
+            crate::utils::clean_orphans(&alive, &pending.save_dirs).ok();
+        }
+        // the parts are kept, they may belong to a task in the unread list
+        Err(e) => tracing::warn!("could not restore the unfinished tasks: {}", e),
+    }
+    std::thread::spawn(|| {
+        for problem in crate::utils::diagnose().problems {
+            tracing::warn!("{}", problem);
+        }
+    });
+    tauri::async_runtime::spawn(async {
+        let profile = crate::config::get_config("bili_profile");
+        match task::Account::of_profile(profile.as_deref()).await {
+            Ok(account) => {
+                let now = chrono::Utc::now().timestamp();
+                // only whether it is logged in and about to expire
+                if let Some(warning) = account.warning(account.max_quality(), now) {
+                    tracing::warn!("bilibili: {}", warning);
+                }
+            }
+            Err(e) => tracing::warn!("could not check the bilibili account: {}", e),
+        }
+    });
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
             echo,
@@ -75,7 +221,20 @@ fn main() {
             remove,
             progress,
             show_config,
-            upgrade_config
+            upgrade_config,
+            config_schema,
+            list_extractors,
+            reload_extractors,
+            export_config,
+            import_config,
+            list_profiles,
+            save_profile,
+            bili_login_qrcode,
+            bili_login_poll,
+            bili_account,
+            import_cookies,
+            export_cookies,
+            diagnostics
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
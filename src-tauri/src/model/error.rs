@@ -1,7 +1,7 @@
 use snafu::prelude::*;
 use uuid::Uuid;
 
-use crate::task::TaskError;
+use crate::{config::error::ConfigError, task::TaskError};
 
 #[derive(Debug, Snafu)]
 #[snafu(module, visibility(pub(crate)), context(suffix(Error)))]
@@ -10,6 +10,18 @@ pub enum BmcError {
     NewTaskError { source: TaskError },
     #[snafu(display("Task id not found: {}", id))]
     TaskNotFound { id: Uuid },
+    #[snafu(display("Cannot find the config dir: {}", source), context(false))]
+    ConfigError { source: ConfigError },
+    #[snafu(
+        display("Cannot keep the unfinished tasks: {}", source),
+        context(false)
+    )]
+    PendingIoError { source: std::io::Error },
+    #[snafu(
+        display("Cannot read the unfinished tasks: {}", source),
+        context(false)
+    )]
+    PendingJsonError { source: serde_json::Error },
 }
 
 pub type BmcResult<T> = Result<T, BmcError>;
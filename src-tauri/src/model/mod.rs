@@ -1,13 +1,14 @@
 mod error;
+mod pending;
 mod task_bmc; // Task Backend Model Controller for task
 
 use std::sync::Arc;
 
-use crate::task::{Info, TaskExe};
+use crate::task::DynTask;
 
 pub use task_bmc::TaskBmc;
 
-pub type Task = Arc<impl TaskExe<Info = impl Info> + Send + Sync>;
+pub type Task = Arc<dyn DynTask>;
 
 pub struct Model {
     pub tasks: Vec<Task>,
@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::error::BmcResult;
+use crate::task::TaskOptions;
+
+const PENDING_FILE: &str = "tasks.json";
+
+/// Held while the file is read and written back
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// The tasks not finished yet, created again with the same id after a restart
+/// so they find their temp folders
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pending {
+    pub tasks: Vec<PendingTask>,
+    /// Every save dir given to a task, whose temp roots are searched for orphans
+    pub save_dirs: Vec<PathBuf>,
+}
+
+impl Pending {
+    fn push(&mut self, id: Uuid, url: &str, options: &TaskOptions) {
+        if let Some(dir) = &options.save_dir {
+            if !self.save_dirs.contains(dir) {
+                self.save_dirs.push(dir.clone());
+            }
+        }
+        self.tasks.push(PendingTask {
+            id: id.to_string(),
+            url: url.to_string(),
+            options: options.clone(),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingTask {
+    pub id: String,
+    pub url: String,
+    /// As given, so the defaults follow the config
+    pub options: TaskOptions,
+}
+
+impl PendingTask {
+    pub fn uuid(&self) -> Option<Uuid> {
+        Uuid::parse_str(&self.id).ok()
+    }
+}
+
+fn path() -> BmcResult<PathBuf> {
+    if cfg!(test) {
+        let dir = std::env::temp_dir().join("downloader_test");
+        std::fs::create_dir_all(&dir)?;
+        return Ok(dir.join(PENDING_FILE));
+    }
+    // every file of the config dir is read as a config value
+    Ok(crate::config::data_dir()?.join(PENDING_FILE))
+}
+
+pub fn load() -> BmcResult<Pending> {
+    let _lock = LOCK.lock().unwrap();
+    read(&path()?)
+}
+
+pub fn add(id: Uuid, url: &str, options: &TaskOptions) -> BmcResult<()> {
+    update(&path()?, |pending| pending.push(id, url, options))
+}
+
+pub fn remove(id: Uuid) -> BmcResult<()> {
+    update(&path()?, |pending| {
+        pending.tasks.retain(|t| t.uuid() != Some(id));
+    })
+}
+
+fn read(path: &Path) -> BmcResult<Pending> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Pending::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn update(path: &Path, f: impl FnOnce(&mut Pending)) -> BmcResult<()> {
+    let _lock = LOCK.lock().unwrap();
+    let mut pending = read(path)?;
+    f(&mut pending);
+    // written aside first, so a crash does not lose the whole list
+    let new = path.with_extension("json_new");
+    std::fs::write(&new, serde_json::to_vec_pretty(&pending)?)?;
+    std::fs::rename(new, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn update_test() {
+        let dir = tempdir::TempDir::new("pending").unwrap();
+        let path = dir.path().join(PENDING_FILE);
+        assert!(read(&path).unwrap().tasks.is_empty());
+        let (kept, done) = (Uuid::new_v4(), Uuid::new_v4());
+        let options = TaskOptions {
+            save_dir: Some(PathBuf::from("/videos")),
+            ..Default::default()
+        };
+        for id in [kept, done] {
+            update(&path, |p| p.push(id, "https://example.com/", &options)).unwrap();
+        }
+        update(&path, |p| p.tasks.retain(|t| t.uuid() != Some(done))).unwrap();
+        let pending = read(&path).unwrap();
+        assert_eq!(pending.tasks.len(), 1);
+        assert_eq!(pending.tasks[0].uuid(), Some(kept));
+        assert_eq!(pending.save_dirs, [PathBuf::from("/videos")]);
+        assert_eq!(
+            pending.tasks[0].options.save_dir.as_deref(),
+            Some(Path::new("/videos"))
+        );
+    }
+}
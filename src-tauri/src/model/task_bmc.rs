@@ -1,19 +1,23 @@
 use super::error::{bmc_error, BmcResult};
+use super::pending::{self, Pending};
 use super::{Model, Task};
-use crate::task::{new_task, TaskExe, TaskResult};
+use crate::task::{new_task, ActorError, TaskError, TaskOptions, TaskResult};
 
 use snafu::OptionExt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot::{self, Sender as OnceSender};
 use uuid::Uuid;
 
-type Message = Option<(String, OnceSender<TaskResult<Task>>)>;
+type Message = Option<(Uuid, String, TaskOptions, OnceSender<TaskResult<Task>>)>;
 
 pub struct TaskBmc {
     model: Model,
     tx: mpsc::Sender<Message>,
     jh: Option<std::thread::JoinHandle<()>>,
+    /// Set on exit, so the tasks cancelled then stay pending
+    closing: Arc<AtomicBool>,
 }
 
 macro_rules! bmc_func {
@@ -36,21 +40,39 @@ macro_rules! bmc_func {
 }
 
 /// title finished total id status
-pub type Process = (String, usize, usize, String, String);
+pub type Process = (String, usize, usize, String, String, String);
 
 impl TaskBmc {
     pub fn new() -> Self {
         let (tx, mut rx) = mpsc::channel::<Message>(8);
+        let closing = Arc::new(AtomicBool::new(false));
+        let task_closing = closing.clone();
         let jh = std::thread::spawn(move || {
             actix_rt::Runtime::new().unwrap().block_on(async move {
                 let mut jhs = Vec::new();
-                while let Some(Some((url, tx))) = rx.recv().await {
-                    let task = new_task(url);
+                while let Some(Some((id, url, options, tx))) = rx.recv().await {
+                    let task = new_task(id, url, options);
                     match task {
                         Ok(task) => {
-                            let task = Arc::new(task);
                             tx.send(Ok(task.clone())).ok();
-                            let jh = actix_rt::spawn(async move { task.go().await });
+                            let closing = task_closing.clone();
+                            let jh = actix_rt::spawn(async move {
+                                let ret = task.go().await;
+                                // a failed task is tried again after a restart
+                                let done = matches!(
+                                    ret,
+                                    Ok(_)
+                                        | Err(TaskError::SaveError {
+                                            source: ActorError::Cancelled
+                                        })
+                                );
+                                if done && !closing.load(Ordering::Relaxed) {
+                                    if let Err(e) = pending::remove(id) {
+                                        tracing::warn!("{}", e);
+                                    }
+                                }
+                                ret
+                            });
                             jhs.push(jh);
                         }
                         Err(e) => {
@@ -68,21 +90,45 @@ impl TaskBmc {
             model: Model::new(),
             tx,
             jh: Some(jh),
+            closing,
         }
     }
 
-    pub fn create<S>(&mut self, url: S) -> BmcResult<Uuid>
+    pub fn create<S>(&mut self, url: S, options: TaskOptions) -> BmcResult<Uuid>
     where
         S: AsRef<str>,
     {
+        let id = Uuid::new_v4();
+        self.spawn(id, url.as_ref(), options.clone())?;
+        if let Err(e) = pending::add(id, url.as_ref(), &options) {
+            tracing::warn!("{}", e);
+        }
+        Ok(id)
+    }
+
+    /// Create the tasks unfinished when the app exited, with their ids.
+    /// A task which can't be created is kept, e.g. its script may be fixed later
+    pub fn restore(&mut self) -> BmcResult<Pending> {
+        let pending = pending::load()?;
+        for task in pending.tasks.iter() {
+            let Some(id) = task.uuid() else {
+                continue;
+            };
+            if let Err(e) = self.spawn(id, &task.url, task.options.clone()) {
+                tracing::warn!("could not restore {}: {}", task.url, e);
+            }
+        }
+        Ok(pending)
+    }
+
+    fn spawn(&mut self, id: Uuid, url: &str, options: TaskOptions) -> BmcResult<()> {
         let (tx, rx) = oneshot::channel::<TaskResult<Task>>();
         self.tx
-            .blocking_send(Some((url.as_ref().to_string(), tx)))
+            .blocking_send(Some((id, url.to_string(), options, tx)))
             .unwrap();
         let new_task = rx.blocking_recv().unwrap()?;
-        let uuid = *new_task.id();
         self.model.tasks.push(new_task);
-        Ok(uuid)
+        Ok(())
     }
 
     pub fn remove(&mut self, id: Uuid) -> BmcResult<()> {
@@ -94,6 +140,7 @@ impl TaskBmc {
             .context(bmc_error::TaskNotFoundError { id })?;
         self.model.tasks[i].cancel()?;
         self.model.tasks.swap_remove(i);
+        pending::remove(id)?;
         Ok(())
     }
 
@@ -101,8 +148,8 @@ impl TaskBmc {
     pub fn progress(&self) -> BmcResult<Vec<Process>> {
         let mut ret = vec![];
         for t in self.model.tasks.iter() {
-            let (filname, finished, total, state) = t.progress_query().unwrap();
-            ret.push((filname, finished, total, t.id().to_string(), state));
+            let (filname, finished, total, state, warning) = t.progress_query().unwrap();
+            ret.push((filname, finished, total, t.id().to_string(), state, warning));
         }
         Ok(ret)
     }
@@ -112,6 +159,7 @@ impl TaskBmc {
 
 impl Drop for TaskBmc {
     fn drop(&mut self) {
+        self.closing.store(true, Ordering::Relaxed);
         self.model.tasks.iter().for_each(|t| {
             t.cancel().ok();
         });
@@ -132,10 +180,15 @@ mod tests {
     fn create_test() {
         let mut task_bmc = TaskBmc::new();
         assert!(task_bmc
-            .create("https://www.bilibili.com/video/BV1NN411F7HE")
+            .create(
+                "https://www.bilibili.com/video/BV1NN411F7HE",
+                TaskOptions::default()
+            )
             .is_ok());
         assert!(task_bmc.model.tasks.len() == 1);
-        assert!(task_bmc.create("should fail").is_err());
+        assert!(task_bmc
+            .create("should fail", TaskOptions::default())
+            .is_err());
         assert!(task_bmc.model.tasks.len() == 1);
     }
 
@@ -144,7 +197,10 @@ mod tests {
     fn bmc_test() {
         let mut task_bmc = TaskBmc::new();
         let id = task_bmc
-            .create("https://www.bilibili.com/video/BV1NN411F7HE")
+            .create(
+                "https://www.bilibili.com/video/BV1NN411F7HE",
+                TaskOptions::default(),
+            )
             .unwrap();
         std::thread::sleep(std::time::Duration::from_secs(2));
         assert!(task_bmc.pause(id).is_ok());
@@ -0,0 +1,34 @@
+use std::{io, path::PathBuf};
+
+use snafu::prelude::*;
+
+use crate::config::error::ConfigError;
+
+#[derive(Debug, Snafu)]
+#[snafu(module, visibility(pub(crate)), context(suffix(Error)))]
+pub enum NetError {
+    #[snafu(display("Bad proxy {}: {}", spec, source))]
+    Proxy {
+        spec: String,
+        source: reqwest::Error,
+    },
+    #[snafu(display("Proxy {} is not http, https, socks5 or socks5h", spec))]
+    ProxyScheme { spec: String },
+    #[snafu(display("Could not read certificate {}: {}", path.display(), source))]
+    CertificateRead { path: PathBuf, source: io::Error },
+    #[snafu(display("Certificate {} is not PEM: {}", path.display(), source))]
+    CertificateParse {
+        path: PathBuf,
+        source: reqwest::Error,
+    },
+    #[snafu(display("Headers are not a json object of strings: {}", source))]
+    HeadersJson { source: serde_json::Error },
+    #[snafu(display("Header {} is not valid", name))]
+    Header { name: String },
+    #[snafu(display("Could not build the http client: {}", source))]
+    Build { source: reqwest::Error },
+    #[snafu(display("{}", source), context(false))]
+    Config { source: ConfigError },
+}
+
+pub type NetResult<T> = Result<T, NetError>;
@@ -0,0 +1,87 @@
+use reqwest::header::{HeaderMap, HeaderValue, ORIGIN, REFERER};
+use snafu::OptionExt;
+
+use super::{
+    error::{net_error, NetResult},
+    parse_headers,
+};
+
+/// The headers `site` expects, sent unless `{site}_headers` in config says otherwise.
+/// `generic` has none, the pages it downloads from differ, so the origin of the page
+/// of each task is sent as `Referer` instead, see [`super::referer_client`]
+fn defaults(site: &str) -> HeaderMap {
+    let pairs = match site {
+        "bili" => vec![
+            (REFERER, "https://www.bilibili.com/"),
+            (ORIGIN, "https://www.bilibili.com"),
+        ],
+        _ => vec![],
+    };
+    pairs
+        .into_iter()
+        .map(|(name, value)| (name, HeaderValue::from_static(value)))
+        .collect()
+}
+
+/// The global headers, then the built-in ones of `site` and the `referer` of the task,
+/// then its `overrides`, where an empty value removes a header.
+/// `User-Agent` in any of them replaces the one in config.
+pub fn site_headers(
+    global: &str,
+    site: Option<&str>,
+    referer: Option<&str>,
+    overrides: &str,
+) -> NetResult<HeaderMap> {
+    let mut headers = parse_headers(global)?;
+    if let Some(site) = site {
+        headers.extend(defaults(site));
+    }
+    if let Some(referer) = referer {
+        let value = HeaderValue::from_str(referer)
+            .ok()
+            .context(net_error::HeaderError { name: "Referer" })?;
+        headers.insert(REFERER, value);
+    }
+    for (name, value) in parse_headers(overrides)?.iter() {
+        match value.is_empty() {
+            true => headers.remove(name),
+            false => headers.insert(name, value.clone()),
+        };
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::{ACCEPT_LANGUAGE, USER_AGENT};
+
+    #[test]
+    fn site_headers_test() {
+        let headers = site_headers(
+            r#"{"Accept-Language": "zh-CN", "Referer": "https://example.com/"}"#,
+            Some("bili"),
+            None,
+            r#"{"Origin": "", "User-Agent": "curl/8.0"}"#,
+        )
+        .unwrap();
+        assert_eq!(headers[ACCEPT_LANGUAGE], "zh-CN");
+        assert_eq!(headers[REFERER], "https://www.bilibili.com/");
+        assert_eq!(headers[USER_AGENT], "curl/8.0");
+        assert!(!headers.contains_key(ORIGIN));
+        assert!(site_headers("", None, None, "").unwrap().is_empty());
+        // the referer of the page is a default, which config overrides
+        let page = Some("https://example.com/");
+        let headers = site_headers("", Some("generic"), page, "").unwrap();
+        assert_eq!(headers[REFERER], "https://example.com/");
+        let headers = site_headers(
+            "",
+            Some("generic"),
+            page,
+            r#"{"Referer": "https://example.org/"}"#,
+        )
+        .unwrap();
+        assert_eq!(headers[REFERER], "https://example.org/");
+        assert!(defaults("unknown").is_empty());
+    }
+}
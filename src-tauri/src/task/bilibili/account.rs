@@ -0,0 +1,158 @@
+use crate::{
+    config::{cookie_key, get_config},
+    net::cookie_client,
+    task::error::TaskResult,
+};
+
+use super::{
+    api::{self, Nav},
+    wbi, BEST_QUALITY,
+};
+
+/// The best quality without logging in, 480P
+const GUEST_QUALITY: u32 = 32;
+/// The best quality without VIP, 1080P
+const MEMBER_QUALITY: u32 = 80;
+/// Warn about a cookie expiring within this many seconds
+const EXPIRY_NOTICE: i64 = 7 * 24 * 3600;
+
+/// What the account of a cookie profile may download, from the nav API
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct Account {
+    pub logged_in: bool,
+    pub name: Option<String>,
+    pub level: Option<u32>,
+    pub vip: bool,
+    /// Unix seconds
+    pub vip_expires: Option<i64>,
+    /// Unix seconds, read from `SESSDATA`
+    pub cookie_expires: Option<i64>,
+}
+
+impl Account {
+    /// The account of a cookie profile of bilibili
+    pub async fn of_profile(profile: Option<&str>) -> TaskResult<Self> {
+        let client = cookie_client("bili", profile)?;
+        let cookie = get_config(cookie_key("bili", profile)).unwrap_or_default();
+        Self::fetch(&client, &cookie).await
+    }
+
+    /// `cookie` is what `client` sends, only read for its expiry
+    pub async fn fetch(client: &reqwest::Client, cookie: &str) -> TaskResult<Self> {
+        let nav = api::nav(client).await?;
+        // saves fetching them again to sign the next requests
+        wbi::remember(&nav);
+        Ok(Self::new(nav, cookie))
+    }
+
+    fn new(nav: Nav, cookie: &str) -> Self {
+        let vip = nav.is_login && nav.vip_status == 1;
+        Self {
+            logged_in: nav.is_login,
+            name: nav.uname.filter(|n| !n.is_empty()),
+            level: nav.level_info.map(|l| l.current_level),
+            vip,
+            vip_expires: (vip && nav.vip_due_date > 0).then_some(nav.vip_due_date / 1000),
+            cookie_expires: sessdata_expires(cookie),
+        }
+    }
+
+    /// The best quality id the account may get
+    pub fn max_quality(&self) -> u32 {
+        match (self.logged_in, self.vip) {
+            (false, _) => GUEST_QUALITY,
+            (true, false) => MEMBER_QUALITY,
+            (true, true) => BEST_QUALITY,
+        }
+    }
+
+    /// Why `quality` may not be downloaded, or the cookie expiring soon.
+    /// [`BEST_QUALITY`] means the best there is, so only a logged out account is warned about.
+    pub fn warning(&self, quality: u32, now: i64) -> Option<String> {
+        let mut warnings = vec![];
+        let wanted = match quality {
+            BEST_QUALITY => MEMBER_QUALITY,
+            quality => quality,
+        };
+        if !self.logged_in {
+            warnings.push("not logged in, the cookie may have expired".to_string());
+        }
+        if wanted > self.max_quality() {
+            warnings.push(format!(
+                "quality {} is above {} the account may get",
+                wanted,
+                self.max_quality()
+            ));
+        }
+        if let Some(expires) = self.cookie_expires.filter(|e| *e - now < EXPIRY_NOTICE) {
+            let date = chrono::DateTime::from_timestamp(expires, 0).unwrap_or_default();
+            warnings.push(format!("the cookie expires on {}", date.format("%Y-%m-%d")));
+        }
+        (!warnings.is_empty()).then(|| warnings.join(", "))
+    }
+}
+
+/// `SESSDATA` is `{token}%2C{expires}%2C{hash}`
+fn sessdata_expires(cookie: &str) -> Option<i64> {
+    let sessdata = cookie
+        .split(';')
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim() == "SESSDATA")?
+        .1
+        .replace("%2C", ",");
+    sessdata.split(',').nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nav(json: &str) -> Nav {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn account_test() {
+        let cookie = "SESSDATA=abc%2C1700000000%2Cdef*11; bili_jct=j";
+        let vip = Account::new(
+            nav(
+                r#"{"isLogin":true,"uname":"me","level_info":{"current_level":6},"vipStatus":1,"vipDueDate":1800000000000}"#,
+            ),
+            cookie,
+        );
+        assert_eq!(vip.level, Some(6));
+        assert_eq!(vip.vip_expires, Some(1_800_000_000));
+        assert_eq!(vip.cookie_expires, Some(1_700_000_000));
+        assert_eq!(vip.max_quality(), BEST_QUALITY);
+        assert_eq!(vip.warning(BEST_QUALITY, 1_600_000_000), None);
+        assert!(vip
+            .warning(BEST_QUALITY, 1_699_999_000)
+            .unwrap()
+            .contains("expires on 2023-11-14"));
+
+        let member = Account::new(nav(r#"{"isLogin":true,"vipStatus":0}"#), "");
+        assert_eq!(member.max_quality(), MEMBER_QUALITY);
+        assert_eq!(member.warning(BEST_QUALITY, 0), None);
+        assert!(member.warning(120, 0).unwrap().contains("above 80"));
+
+        let guest = Account::new(nav(r#"{"isLogin":false}"#), "");
+        assert!(!guest.vip);
+        assert!(guest
+            .warning(BEST_QUALITY, 0)
+            .unwrap()
+            .starts_with("not logged in"));
+        assert_eq!(
+            guest.warning(GUEST_QUALITY, 0).unwrap(),
+            "not logged in, the cookie may have expired"
+        );
+    }
+
+    #[test]
+    fn sessdata_test() {
+        assert_eq!(
+            sessdata_expires("SESSDATA=a,1700000000,b"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(sessdata_expires("bili_jct=j"), None);
+    }
+}
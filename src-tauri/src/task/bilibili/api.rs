@@ -0,0 +1,257 @@
+use serde::{Deserialize, Deserializer};
+use snafu::OptionExt;
+
+use crate::task::{
+    error::{parse_error, task_error, TaskResult},
+    info::BiliInfo,
+};
+
+use super::wbi;
+
+const VIEW_API: &str = "https://api.bilibili.com/x/web-interface/wbi/view";
+const PAGELIST_API: &str = "https://api.bilibili.com/x/player/pagelist";
+const PLAYURL_API: &str = "https://api.bilibili.com/x/player/wbi/playurl";
+const NAV_API: &str = "https://api.bilibili.com/x/web-interface/nav";
+
+/// What every bilibili API answers with, `data` is only there if `code` is 0
+#[derive(Debug, Deserialize)]
+pub struct Response<T> {
+    pub code: i64,
+    #[serde(default)]
+    pub message: String,
+    pub data: Option<T>,
+}
+
+impl<T> Response<T> {
+    /// `data`, or the error `code` stands for
+    pub fn into_data(self) -> TaskResult<T> {
+        let Response {
+            code,
+            message,
+            data,
+        } = self;
+        match (code, data) {
+            (0, Some(data)) => Ok(data),
+            (0, None) => Err(parse_error::InfoNotFound.build().into()),
+            (-101, _) => task_error::LoginRequired.fail(),
+            (-10403 | 6002003, _) => task_error::RegionLocked.fail(),
+            (-404 | 62002 | 62004 | 62012, _) => {
+                task_error::VideoUnavailableError { message }.fail()
+            }
+            (-403 | 87008, _) => task_error::AccessDeniedError { message }.fail(),
+            (-352 | -412, _) => task_error::RequestBlockedError { message }.fail(),
+            (code, _) => task_error::ApiError { code, message }.fail(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct View {
+    pub bvid: String,
+    pub title: String,
+    /// Of the first page
+    pub cid: u64,
+    pub owner: Owner,
+    /// Unix seconds
+    pub pubdate: i64,
+    #[serde(default)]
+    pub pages: Vec<Page>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Owner {
+    pub mid: u64,
+    pub name: String,
+}
+
+/// A part of a video with several
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    pub cid: u64,
+    /// From 1
+    pub page: u32,
+    pub part: String,
+    /// Seconds
+    pub duration: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayUrl {
+    pub quality: u32,
+    /// The qualities the account may get, best first
+    pub accept_quality: Vec<u32>,
+    pub dash: Dash,
+}
+
+/// Streams are worst first, the order bilibili sends them in reversed
+#[derive(Debug, Deserialize)]
+pub struct Dash {
+    #[serde(deserialize_with = "streams")]
+    pub video: Vec<BiliInfo>,
+    #[serde(default, deserialize_with = "streams")]
+    pub audio: Vec<BiliInfo>,
+    pub flac: Option<Flac>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Flac {
+    pub audio: Option<BiliInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Nav {
+    #[serde(rename = "isLogin")]
+    pub is_login: bool,
+    pub uname: Option<String>,
+    pub level_info: Option<LevelInfo>,
+    #[serde(rename = "vipStatus", default)]
+    pub vip_status: u8,
+    /// Unix milliseconds
+    #[serde(rename = "vipDueDate", default)]
+    pub vip_due_date: i64,
+    pub wbi_img: Option<WbiImg>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LevelInfo {
+    pub current_level: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WbiImg {
+    pub img_url: String,
+    pub sub_url: String,
+}
+
+/// Streams of unknown formats are skipped rather than failing the others
+fn streams<'de, D>(deserializer: D) -> Result<Vec<BiliInfo>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Option::<Vec<serde_json::Value>>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .flatten()
+        .rev()
+        .filter_map(|v| serde_json::from_value(v).ok())
+        .collect())
+}
+
+async fn get<T>(client: &reqwest::Client, api: &str, query: &[(String, String)]) -> TaskResult<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let resp = client.get(api).query(query).send().await?;
+    resp.json::<Response<T>>().await?.into_data()
+}
+
+pub async fn view(client: &reqwest::Client, bvid: &str) -> TaskResult<View> {
+    let query = wbi::sign(client, &[("bvid", bvid)]).await?;
+    get(client, VIEW_API, &query).await
+}
+
+pub async fn pagelist(client: &reqwest::Client, bvid: &str) -> TaskResult<Vec<Page>> {
+    get(
+        client,
+        PAGELIST_API,
+        &[("bvid".to_string(), bvid.to_string())],
+    )
+    .await
+}
+
+pub async fn playurl(
+    client: &reqwest::Client,
+    bvid: &str,
+    cid: u64,
+    quality: u32,
+) -> TaskResult<PlayUrl> {
+    let query = wbi::sign(
+        client,
+        &[
+            ("bvid", bvid),
+            ("cid", &cid.to_string()),
+            ("qn", &quality.to_string()),
+            ("fourk", "1"),
+            ("fnval", "1040"), // 16 | 1024 = 1040
+            ("fnver", "0"),
+        ],
+    )
+    .await?;
+    get(client, PLAYURL_API, &query).await
+}
+
+/// Also answered when logged out, with `code` -101
+pub async fn nav(client: &reqwest::Client) -> TaskResult<Nav> {
+    let resp = client.get(NAV_API).send().await?;
+    let nav = resp.json::<Response<Nav>>().await?;
+    Ok(nav.data.context(parse_error::InfoNotFound)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::task::TaskError;
+
+    #[test]
+    fn view_test() {
+        let view = serde_json::from_str::<Response<View>>(include_str!(
+            "../../../../example/bili_info.json"
+        ))
+        .unwrap()
+        .into_data()
+        .unwrap();
+        assert_eq!(view.bvid, "BV1EC4y1V7ho");
+        assert_eq!(view.cid, 1303248673);
+        assert_eq!(view.pages.len(), 1);
+        assert_eq!(view.pages[0].page, 1);
+    }
+
+    #[test]
+    fn playurl_test() {
+        let playurl = serde_json::from_str::<Response<PlayUrl>>(include_str!(
+            "../../../../example/bili_video_info.json"
+        ))
+        .unwrap()
+        .into_data()
+        .unwrap();
+        let ids = playurl.dash.video.iter().map(|v| v.id).collect::<Vec<_>>();
+        assert_eq!(ids.first(), Some(&16));
+        assert_eq!(ids.last(), Some(&112));
+        assert!(!playurl.dash.audio.is_empty());
+        assert!(playurl.dash.flac.is_none());
+    }
+
+    #[test]
+    fn code_test() {
+        let error = |json: &str| {
+            serde_json::from_str::<Response<View>>(json)
+                .unwrap()
+                .into_data()
+                .unwrap_err()
+        };
+        assert!(matches!(
+            error(r#"{"code":-101,"message":"账号未登录"}"#),
+            TaskError::LoginRequired
+        ));
+        assert!(matches!(
+            error(r#"{"code":-10403,"message":"抱歉您所在地区不可观看！"}"#),
+            TaskError::RegionLocked
+        ));
+        assert!(matches!(
+            error(r#"{"code":62002,"message":"稿件不可见"}"#),
+            TaskError::VideoUnavailable { .. }
+        ));
+        assert!(matches!(
+            error(r#"{"code":-412,"message":"请求被拦截"}"#),
+            TaskError::RequestBlocked { .. }
+        ));
+        assert!(matches!(
+            error(r#"{"code":-500,"message":"服务器错误"}"#),
+            TaskError::Api { code: -500, .. }
+        ));
+        assert!(matches!(
+            error(r#"{"code":0,"message":"0"}"#),
+            TaskError::ParseHtmlError { .. }
+        ));
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use actix::{Actor, Addr};
+use snafu::OptionExt;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{config::get_config, utils::DownloadMode};
+
+use super::{
+    cookie_key,
+    error::TaskResult,
+    extractor::Extractor,
+    info::BiliInfo,
+    task_actor::{SetWarning, TaskActor},
+    task_error, DynTask, Meta, TaskExe, TaskOptions,
+};
+
+mod account;
+mod api;
+mod wbi;
+
+pub use account::Account;
+
+/// The best quality bilibili offers, 8K
+const BEST_QUALITY: u32 = 127;
+/// Short links shared from the app, redirecting to the video
+const SHORT_HOST: &str = "b23.tv";
+
+pub struct BiliExtractor;
+
+impl Extractor for BiliExtractor {
+    fn name(&self) -> &str {
+        "bili"
+    }
+
+    fn patterns(&self) -> Vec<String> {
+        vec!["*.bilibili.com/video/".to_string(), SHORT_HOST.to_string()]
+    }
+
+    fn extract(&self, id: Uuid, url: Url, options: TaskOptions) -> TaskResult<Arc<dyn DynTask>> {
+        Ok(Arc::new(BiliTask::new(id, url, options)?))
+    }
+}
+
+pub struct BiliTask {
+    id: Uuid,
+    url: Url,
+    addr: Addr<TaskActor>,
+    options: TaskOptions,
+}
+
+impl BiliTask {
+    pub fn new<S>(id: Uuid, url: S, options: TaskOptions) -> TaskResult<Self>
+    where
+        S: AsRef<str>,
+    {
+        Ok(Self {
+            id,
+            url: Url::parse(url.as_ref())?,
+            addr: TaskActor::new().start(),
+            options,
+        })
+    }
+}
+
+impl TaskExe for BiliTask {
+    type Info = BiliInfo;
+
+    async fn get_child_tasks(&self) -> TaskResult<(Meta, Vec<Self::Info>)> {
+        // sends the cookies of the profile, and keeps them fresh
+        let client = self.client()?;
+
+        let url = match self.url.host_str() {
+            Some(SHORT_HOST) => client.get(self.url.clone()).send().await?.url().clone(),
+            _ => self.url.clone(),
+        };
+        let bvid = url
+            .path_segments()
+            .context(task_error::BvidNotFound)?
+            .nth(1)
+            .context(task_error::BvidNotFound)?;
+
+        let quality = self.options.quality.unwrap_or(BEST_QUALITY);
+        // a logged out cookie is only noticed in the quality of the file otherwise,
+        // and it fetches the keys to sign with
+        match Account::fetch(&client, &self.cookie().unwrap_or_default()).await {
+            Ok(account) => {
+                let warning = account.warning(quality, chrono::Utc::now().timestamp());
+                if let Some(warning) = &warning {
+                    tracing::warn!("{}: {}", self.url, warning);
+                }
+                self.addr.send(SetWarning(warning)).await??;
+            }
+            Err(e) => tracing::warn!("could not check the account: {}", e),
+        }
+
+        let view = api::view(&client, bvid).await?;
+        // `?p=2` for the second part of a video with several
+        let page = url
+            .query_pairs()
+            .find(|(k, _)| k == "p")
+            .and_then(|(_, p)| p.parse::<u32>().ok())
+            .unwrap_or(1);
+        let pages = match view.pages.is_empty() {
+            true => api::pagelist(&client, bvid).await?,
+            false => view.pages,
+        };
+        let part = pages.iter().find(|p| p.page == page);
+        let cid = part.map(|p| p.cid).unwrap_or(view.cid);
+        let mut meta = Meta::new(view.title)
+            .field("bvid", bvid)
+            .field("id", bvid)
+            .field("cid", cid.to_string())
+            .field("uploader", view.owner.name);
+        if let Some(part) = part.filter(|_| pages.len() > 1) {
+            meta = meta
+                .field("page", part.page.to_string())
+                .field("part", &part.part);
+        }
+        if let Some(date) = chrono::DateTime::from_timestamp(view.pubdate, 0) {
+            meta = meta.field("date", date.format("%Y-%m-%d").to_string());
+        }
+
+        let dash = api::playurl(&client, bvid, cid, quality).await?.dash;
+        let mut videos = dash.video;
+        let mut audios = dash.audio;
+        // ascending, so the last one not above the preference is the best allowed
+        let video = match videos.iter().rposition(|v| v.id <= quality) {
+            Some(i) => videos.swap_remove(i),
+            None if !videos.is_empty() => videos.swap_remove(0),
+            None => task_error::StreamNotFound.fail()?,
+        };
+        let audio = audios.pop().context(task_error::StreamNotFound)?;
+        let infos = match self.options.mode.unwrap_or_default() {
+            DownloadMode::Both => vec![video, audio],
+            DownloadMode::VideoOnly => vec![video],
+            // the lossless one if the account can get it
+            DownloadMode::AudioOnly => {
+                vec![dash.flac.and_then(|f| f.audio).unwrap_or(audio)]
+            }
+        };
+        Ok((meta, infos))
+    }
+
+    fn cookie(&self) -> TaskResult<String> {
+        get_config(cookie_key("bili", &self.options)).context(task_error::ConfigNotFound)
+    }
+
+    fn addr(&self) -> &actix::Addr<super::task_actor::TaskActor> {
+        &self.addr
+    }
+
+    fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn options(&self) -> &TaskOptions {
+        &self.options
+    }
+
+    fn site(&self) -> &str {
+        "bili"
+    }
+}
+
+#[cfg(test)]
+mod bilibili_test {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn bili_child_task_test() {
+        let task = BiliTask::new(
+            Uuid::new_v4(),
+            "https://www.bilibili.com/video/BV1EC4y1V7ho",
+            Default::default(),
+        )
+        .unwrap();
+        let (meta, infos) = task.get_child_tasks().await.unwrap();
+        assert!(!meta.title.is_empty());
+        assert_eq!(meta.fields["title"], meta.title);
+        assert_eq!(meta.fields["bvid"], "BV1EC4y1V7ho");
+        assert_eq!(meta.fields["id"], "BV1EC4y1V7ho");
+        for key in ["cid", "uploader", "date"] {
+            assert!(meta.fields.contains_key(key), "{key} is missing");
+        }
+        assert_eq!(meta.fields["date"].len(), "2023-11-01".len());
+        // a video and an audio in the default mode
+        assert_eq!(infos.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_bilibili() {
+        let task = BiliTask::new(
+            Uuid::new_v4(),
+            "https://www.bilibili.com/video/BV1EC4y1V7ho",
+            Default::default(),
+        )
+        .unwrap();
+        TaskExe::go(&task).await.unwrap();
+    }
+}
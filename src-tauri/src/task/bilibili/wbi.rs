@@ -0,0 +1,148 @@
+use std::sync::Mutex;
+
+use snafu::OptionExt;
+
+use crate::task::error::{parse_error, TaskResult};
+
+use super::api::{self, Nav};
+
+/// The order the characters of the img and sub keys are mixed in
+const MIXIN_KEY_ENC_TAB: [usize; 64] = [
+    46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
+    28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
+    54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
+];
+/// The keys change daily, they are fetched again after this many seconds
+const KEY_TTL: i64 = 3600;
+
+/// The mixin key and when it was fetched
+static MIXIN_KEY: Mutex<Option<(String, i64)>> = Mutex::new(None);
+
+/// `params` with `wts` and `w_rid` added, as the wbi APIs ask for
+pub async fn sign(
+    client: &reqwest::Client,
+    params: &[(&str, &str)],
+) -> TaskResult<Vec<(String, String)>> {
+    let now = chrono::Utc::now().timestamp();
+    let cached = MIXIN_KEY
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|(_, fetched)| now - fetched < KEY_TTL);
+    let key = match cached {
+        Some((key, _)) => key,
+        None => remember(&api::nav(client).await?).context(parse_error::InfoNotFound)?,
+    };
+    Ok(signed(params, &key, now))
+}
+
+/// Cache the keys in a nav response, so they are not fetched again for signing
+pub(super) fn remember(nav: &Nav) -> Option<String> {
+    let img = nav.wbi_img.as_ref()?;
+    let key = mixin_key(&key_of(&img.img_url), &key_of(&img.sub_url));
+    *MIXIN_KEY.lock().unwrap() = Some((key.clone(), chrono::Utc::now().timestamp()));
+    Some(key)
+}
+
+/// `https://i0.hdslb.com/bfs/wbi/{key}.png`
+fn key_of(url: &str) -> String {
+    let name = url.rsplit('/').next().unwrap_or_default();
+    name.split('.').next().unwrap_or_default().to_string()
+}
+
+fn mixin_key(img_key: &str, sub_key: &str) -> String {
+    let raw = [img_key.as_bytes(), sub_key.as_bytes()].concat();
+    MIXIN_KEY_ENC_TAB
+        .iter()
+        .filter_map(|i| raw.get(*i).map(|c| *c as char))
+        .take(32)
+        .collect()
+}
+
+fn signed(params: &[(&str, &str)], mixin_key: &str, wts: i64) -> Vec<(String, String)> {
+    let mut params = params
+        .iter()
+        .map(|(k, v)| {
+            // removed by bilibili before checking the signature
+            let v = v.chars().filter(|c| !"!'()*".contains(*c)).collect();
+            (k.to_string(), v)
+        })
+        .chain([("wts".to_string(), wts.to_string())])
+        .collect::<Vec<(String, String)>>();
+    params.sort();
+    let query = params
+        .iter()
+        .map(|(k, v)| format!("{}={}", encode(k), encode(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let w_rid = format!("{:x}", md5::compute(query + mixin_key));
+    params.push(("w_rid".to_string(), w_rid));
+    params
+}
+
+/// Like `encodeURIComponent`, which bilibili signs with
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IMG_KEY: &str = "7cd084941338484aae1ad9425b84077c";
+    const SUB_KEY: &str = "4932caff0ff746eab6f01bf08b70ac45";
+
+    #[test]
+    fn mixin_key_test() {
+        assert_eq!(
+            mixin_key(IMG_KEY, SUB_KEY),
+            "ea1db124af3c7062474693fa704f4ff8"
+        );
+        assert_eq!(
+            key_of("https://i0.hdslb.com/bfs/wbi/7cd084941338484aae1ad9425b84077c.png"),
+            IMG_KEY
+        );
+    }
+
+    #[test]
+    fn sign_test() {
+        let params = [("foo", "114"), ("bar", "514"), ("zab", "1919810")];
+        let signed = signed(&params, &mixin_key(IMG_KEY, SUB_KEY), 1702204169);
+        assert_eq!(
+            signed.last().unwrap(),
+            &(
+                "w_rid".to_string(),
+                "8f6f2b5b3d485fe1886cec6a0be8c5d4".to_string()
+            )
+        );
+        assert_eq!(signed[0].0, "bar");
+        assert!(signed.contains(&("wts".to_string(), "1702204169".to_string())));
+    }
+
+    #[test]
+    fn encode_test() {
+        assert_eq!(encode("a b+c/中"), "a%20b%2Bc%2F%E4%B8%AD");
+        let signed = signed(&[("q", "(x)!*'")], "", 0);
+        assert_eq!(signed[0].1, "x");
+    }
+
+    #[test]
+    fn remember_test() {
+        let nav = serde_json::from_value::<Nav>(serde_json::json!({
+            "isLogin": false,
+            "wbi_img": {
+                "img_url": format!("https://i0.hdslb.com/bfs/wbi/{IMG_KEY}.png"),
+                "sub_url": format!("https://i0.hdslb.com/bfs/wbi/{SUB_KEY}.png"),
+            }
+        }))
+        .unwrap();
+        assert_eq!(remember(&nav).unwrap(), "ea1db124af3c7062474693fa704f4ff8");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::utils::TemDirError;
 use reqwest::Error as ReqwestError;
 use snafu::prelude::*;
 
@@ -8,18 +9,47 @@ pub enum TaskError {
     ParseUrl { source: url::ParseError },
     #[snafu(display("Could not parse bvid"), context(suffix(false)))]
     BvidNotFound,
+    #[snafu(display("No stream to download"), context(suffix(false)))]
+    StreamNotFound,
     #[snafu(display("Maybe network disconnected"), context(false))]
     GetError { source: ReqwestError },
     #[snafu(context(false))]
     ParseHtmlError { source: ParseError },
     #[snafu()]
     StatusError,
+    #[snafu(
+        display("Log in for this, the cookie may have expired"),
+        context(suffix(false))
+    )]
+    LoginRequired,
+    #[snafu(display("Not available in your region"), context(suffix(false)))]
+    RegionLocked,
+    #[snafu(display("The video was removed or is not visible: {}", message))]
+    VideoUnavailable { message: String },
+    #[snafu(display("Access denied: {}", message))]
+    AccessDenied { message: String },
+    #[snafu(display("Blocked by bilibili, try again later: {}", message))]
+    RequestBlocked { message: String },
+    #[snafu(display("bilibili answered {}: {}", code, message))]
+    Api { code: i64, message: String },
     #[snafu(context(suffix(false)))]
     UnknownTaskType,
     #[snafu(context(suffix(false)))]
     ConfigNotFound,
+    #[snafu(display("{}", source), context(false))]
+    Config {
+        source: crate::config::error::ConfigError,
+    },
+    #[snafu(display("{}", source), context(false))]
+    Net { source: crate::net::error::NetError },
+    #[snafu(display("Extractor script {} failed: {}", name, message))]
+    Script { name: String, message: String },
+    #[snafu(display("Unknown post processor: {}", spec), context(suffix(false)))]
+    UnknownPostProcessor { spec: String },
     #[snafu(context(false))]
     SaveError { source: ActorError },
+    #[snafu(display("{}", source), context(false))]
+    DiskError { source: TemDirError },
     #[snafu(context(false))]
     ActixError { source: actix::MailboxError },
 }
@@ -50,6 +80,15 @@ pub enum ActorError {
     NetWorkError { source: reqwest::Error },
     #[snafu(context(suffix(false)))]
     Cancelled,
+    #[snafu(display("Could not write to disk: {}", source), context(false))]
+    WriteError { source: TemDirError },
+    #[snafu(display("Could not merge: {}", source))]
+    MergeError { source: TemDirError },
+    #[snafu(
+        display("The download stopped without a result"),
+        context(suffix(false))
+    )]
+    Stopped,
 }
 
 pub type ActorResult<T> = Result<T, ActorError>;
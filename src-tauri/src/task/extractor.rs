@@ -0,0 +1,136 @@
+use std::sync::{Arc, OnceLock, RwLock};
+
+use url::Url;
+use uuid::Uuid;
+
+use super::{
+    bilibili::BiliExtractor, error::TaskResult, generic::GenericExtractor, DynTask, TaskOptions,
+};
+
+/// The built-in extractors, urls are matched against them in order,
+/// so the one matching any page comes last
+static BUILTIN: OnceLock<Vec<Arc<dyn Extractor>>> = OnceLock::new();
+/// Loaded from scripts, tried before the built-in ones so a script can take over a site
+static SCRIPTED: RwLock<Vec<Arc<dyn Extractor>>> = RwLock::new(Vec::new());
+
+/// Turns the urls of a site into tasks
+pub trait Extractor: Send + Sync {
+    /// Also the config prefix of the site, e.g. `bili` for `bili_cookie`
+    fn name(&self) -> &str;
+
+    /// `host` or `host/path`, the url matches if its host is the same and its path starts with `path`.
+    /// A host of `*.example.com` matches its subdomains as well.
+    fn patterns(&self) -> Vec<String>;
+
+    fn matches(&self, url: &Url) -> bool {
+        self.patterns().iter().any(|p| pattern_matches(p, url))
+    }
+
+    /// The task downloading `url`, `options` are already filled from config.
+    /// `id` is kept across restarts, it names the temp folder of the task
+    fn extract(&self, id: Uuid, url: Url, options: TaskOptions) -> TaskResult<Arc<dyn DynTask>>;
+}
+
+/// What the UI shows of an extractor
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ExtractorInfo {
+    pub name: String,
+    pub patterns: Vec<String>,
+    pub scripted: bool,
+}
+
+fn builtin() -> &'static [Arc<dyn Extractor>] {
+    BUILTIN.get_or_init(|| vec![Arc::new(BiliExtractor), Arc::new(GenericExtractor)])
+}
+
+/// Replace the extractors loaded from scripts
+pub fn set_scripted(extractors: Vec<Arc<dyn Extractor>>) {
+    *SCRIPTED.write().unwrap() = extractors;
+}
+
+/// The first extractor matching `url`
+pub fn find(url: &Url) -> Option<Arc<dyn Extractor>> {
+    let scripted = SCRIPTED.read().unwrap();
+    scripted
+        .iter()
+        .chain(builtin())
+        .find(|e| e.matches(url))
+        .cloned()
+}
+
+pub fn list() -> Vec<ExtractorInfo> {
+    let scripted = SCRIPTED.read().unwrap();
+    let info = |e: &Arc<dyn Extractor>, scripted| ExtractorInfo {
+        name: e.name().to_string(),
+        patterns: e.patterns(),
+        scripted,
+    };
+    scripted
+        .iter()
+        .map(|e| info(e, true))
+        .chain(builtin().iter().map(|e| info(e, false)))
+        .collect()
+}
+
+fn pattern_matches(pattern: &str, url: &Url) -> bool {
+    let (host, path) = match pattern.split_once('/') {
+        Some((host, path)) => (host, path),
+        None => (pattern, ""),
+    };
+    let host_matches = url
+        .host_str()
+        .is_some_and(|h| match host.strip_prefix("*.") {
+            Some(domain) => h == domain || h.ends_with(&format!(".{domain}")),
+            None => h == host,
+        });
+    host_matches && url.path().trim_start_matches('/').starts_with(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pattern_test() {
+        let url = |s: &str| Url::parse(s).unwrap();
+        assert!(pattern_matches(
+            "www.bilibili.com/video/",
+            &url("https://www.bilibili.com/video/BV1EC4y1V7ho")
+        ));
+        assert!(!pattern_matches(
+            "www.bilibili.com/video/",
+            &url("https://www.bilibili.com/bangumi/play/ep1")
+        ));
+        assert!(pattern_matches(
+            "*.example.com",
+            &url("https://cdn.example.com/a.mp4")
+        ));
+        assert!(pattern_matches(
+            "*.example.com",
+            &url("https://example.com/")
+        ));
+        assert!(!pattern_matches(
+            "*.example.com",
+            &url("https://notexample.com/")
+        ));
+    }
+
+    #[test]
+    fn registry_test() {
+        let bili = find(&Url::parse("https://www.bilibili.com/video/BV1EC4y1V7ho").unwrap());
+        assert_eq!(bili.unwrap().name(), "bili");
+        let page = find(&Url::parse("https://example.org/").unwrap());
+        assert_eq!(page.unwrap().name(), "generic");
+        assert!(find(&Url::parse("file:///a.mp4").unwrap()).is_none());
+        assert!(list().iter().any(|e| e.name == "bili"));
+        for bili in [
+            "https://bilibili.com/video/BV1EC4y1V7ho",
+            "https://m.bilibili.com/video/BV1EC4y1V7ho?p=2",
+            "https://b23.tv/aBcDeF1",
+        ] {
+            assert_eq!(find(&Url::parse(bili).unwrap()).unwrap().name(), "bili");
+        }
+        let space = find(&Url::parse("https://space.bilibili.com/1").unwrap());
+        assert_eq!(space.unwrap().name(), "generic");
+    }
+}
@@ -0,0 +1,494 @@
+use std::sync::Arc;
+
+use actix::{Actor, Addr};
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde_json::Value;
+use url::Url;
+use uuid::Uuid;
+
+use super::{
+    error::{task_error, TaskResult},
+    extractor::Extractor,
+    info::StreamInfo,
+    task_actor::TaskActor,
+    DynTask, Meta, TaskExe, TaskOptions,
+};
+
+const SITE: &str = "generic";
+const VIDEO_EXTS: &[&str] = &["mp4", "m4v", "webm", "mkv", "mov", "flv"];
+const AUDIO_EXTS: &[&str] = &["m4a", "mp3", "aac", "ogg", "opus", "flac", "wav"];
+const PLAYLIST_EXTS: &[&str] = &["m3u8", "mpd"];
+/// Links in inline scripts, which may be escaped as `\/` in json
+const SCRIPT_LINK: &str = r#"https?://[^"'\s<>\\]+?\.(?:m3u8|mpd|mp4)(?:\?[^"'\s<>\\]*)?"#;
+
+/// Any http or https page, tried after every other extractor
+pub struct GenericExtractor;
+
+impl Extractor for GenericExtractor {
+    fn name(&self) -> &str {
+        SITE
+    }
+
+    fn patterns(&self) -> Vec<String> {
+        vec!["*".to_string()]
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "http" | "https") && url.has_host()
+    }
+
+    fn extract(&self, id: Uuid, url: Url, options: TaskOptions) -> TaskResult<Arc<dyn DynTask>> {
+        Ok(Arc::new(GenericPageTask::new(id, url, options)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Video,
+    Audio,
+    /// HLS or DASH, downloaded by ffmpeg
+    Playlist,
+}
+
+impl Kind {
+    /// From the extension of `url`, if it is a known one
+    fn of_url(url: &Url) -> Option<Self> {
+        let ext = std::path::Path::new(url.path())
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            e if PLAYLIST_EXTS.contains(&e) => Some(Kind::Playlist),
+            e if VIDEO_EXTS.contains(&e) => Some(Kind::Video),
+            e if AUDIO_EXTS.contains(&e) => Some(Kind::Audio),
+            _ => None,
+        }
+    }
+
+    /// From a media type, `None` for anything not downloadable, e.g. a player page
+    fn of_mime(mime: &str) -> Option<Self> {
+        let mime = mime.trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/x-mpegurl" | "application/vnd.apple.mpegurl" | "application/dash+xml" => {
+                Some(Kind::Playlist)
+            }
+            m if m.starts_with("video/") => Some(Kind::Video),
+            m if m.starts_with("audio/") => Some(Kind::Audio),
+            _ => None,
+        }
+    }
+
+    /// The suffix of a file whose url has no known extension
+    fn suffix(&self) -> &'static str {
+        match self {
+            Kind::Video => "mp4",
+            Kind::Audio => "m4a",
+            Kind::Playlist => "m3u8",
+        }
+    }
+}
+
+/// An `og:video` or `og:audio` with the structured properties following it
+struct OgMedia {
+    kind: Kind,
+    urls: Vec<String>,
+    mime: Option<String>,
+}
+
+/// What a page links to, the ones the page declares as its media first
+#[derive(Debug, Default)]
+struct Sniffed {
+    title: Option<String>,
+    date: Option<String>,
+    media: Vec<(Url, Kind)>,
+}
+
+impl Sniffed {
+    /// `kind` is only used if the extension of `src` does not tell
+    fn push(&mut self, base: &Url, src: &str, kind: Kind) {
+        let Ok(url) = base.join(src.trim()) else {
+            return;
+        };
+        let kind = Kind::of_url(&url).unwrap_or(kind);
+        if matches!(url.scheme(), "http" | "https") && !self.media.iter().any(|(u, _)| *u == url) {
+            self.media.push((url, kind));
+        }
+    }
+
+    /// The first video or playlist, or the first audio if there is none
+    fn best(&self) -> TaskResult<&(Url, Kind)> {
+        let video = self.media.iter().find(|(_, k)| *k != Kind::Audio);
+        match video.or(self.media.first()) {
+            Some(media) => Ok(media),
+            None => task_error::StreamNotFound.fail(),
+        }
+    }
+}
+
+/// Open Graph tags and `VideoObject`s of JSON-LD, which name the media of the page,
+/// then `<video>`, `<audio>` and their `<source>`s, which may be ads,
+/// then links in inline scripts.
+/// An Open Graph tag is often a player page, it is only taken when its
+/// `og:video:type` or its extension is of a media
+fn sniff(html: &str, base: &Url) -> Sniffed {
+    let document = Html::parse_document(html);
+    let select = |selector: &str| Selector::parse(selector).unwrap();
+    let mut sniffed = Sniffed::default();
+
+    let mut og: Vec<OgMedia> = vec![];
+    for element in document.select(&select(r#"meta[property^="og:"][content]"#)) {
+        let (Some(property), Some(content)) = (
+            element.value().attr("property"),
+            element.value().attr("content"),
+        ) else {
+            continue;
+        };
+        let property = &property["og:".len()..];
+        if property == "title" && sniffed.title.is_none() {
+            sniffed.title = Some(content.to_string());
+        }
+        let (root, field) = property.split_once(':').unwrap_or((property, ""));
+        let kind = match root {
+            "video" => Kind::Video,
+            "audio" => Kind::Audio,
+            _ => continue,
+        };
+        if field.is_empty() || !og.last().is_some_and(|m| m.kind == kind) {
+            og.push(OgMedia {
+                kind,
+                urls: vec![],
+                mime: None,
+            });
+        }
+        let media = og.last_mut().unwrap();
+        match field {
+            "secure_url" => media.urls.insert(0, content.to_string()),
+            "" | "url" => media.urls.push(content.to_string()),
+            "type" => media.mime = Some(content.to_string()),
+            _ => {}
+        }
+    }
+    for media in og {
+        let mime = media.mime.as_deref().and_then(Kind::of_mime);
+        for src in media.urls {
+            let known = base.join(src.trim()).ok().and_then(|u| Kind::of_url(&u));
+            match mime.or(known) {
+                Some(kind) => sniffed.push(base, &src, kind),
+                None => tracing::debug!("skipped og:{:?} {}, not a media", media.kind, src),
+            }
+        }
+    }
+
+    for script in document.select(&select(r#"script[type="application/ld+json"]"#)) {
+        let Ok(json) = serde_json::from_str::<Value>(&script.text().collect::<String>()) else {
+            continue;
+        };
+        for object in media_objects(&json) {
+            let kind = match object["@type"].as_str() {
+                Some("AudioObject") => Kind::Audio,
+                _ => Kind::Video,
+            };
+            if let Some(src) = object["contentUrl"].as_str() {
+                sniffed.push(base, src, kind);
+            }
+            if sniffed.title.is_none() {
+                sniffed.title = object["name"].as_str().map(str::to_string);
+            }
+            if sniffed.date.is_none() {
+                sniffed.date = object["uploadDate"]
+                    .as_str()
+                    .and_then(|d| d.get(..10))
+                    .map(str::to_string);
+            }
+        }
+    }
+
+    for (selector, kind) in [
+        ("video[src], video source[src]", Kind::Video),
+        ("audio[src], audio source[src]", Kind::Audio),
+    ] {
+        for element in document.select(&select(selector)) {
+            sniffed.push(base, element.value().attr("src").unwrap_or_default(), kind);
+        }
+    }
+
+    let link = Regex::new(SCRIPT_LINK).unwrap();
+    for script in document.select(&select("script:not([src])")) {
+        let text = script.text().collect::<String>().replace(r"\/", "/");
+        for m in link.find_iter(&text) {
+            sniffed.push(base, m.as_str(), Kind::Video);
+        }
+    }
+
+    if sniffed.title.is_none() {
+        sniffed.title = document
+            .select(&select("title"))
+            .next()
+            .map(|t| t.text().collect::<String>().trim().to_string())
+            .filter(|t| !t.is_empty());
+    }
+    sniffed
+}
+
+/// The `VideoObject`s and `AudioObject`s anywhere in a JSON-LD document
+fn media_objects(json: &Value) -> Vec<&Value> {
+    match json {
+        Value::Array(values) => values.iter().flat_map(media_objects).collect(),
+        Value::Object(object) => {
+            let mut found = match object.get("@type").and_then(Value::as_str) {
+                Some("VideoObject" | "AudioObject") => vec![json],
+                _ => vec![],
+            };
+            found.extend(object.values().flat_map(media_objects));
+            found
+        }
+        _ => vec![],
+    }
+}
+
+/// The meta and the stream of a page at `base`, the url it was redirected to
+fn page_infos(html: &str, base: &Url) -> TaskResult<(Meta, Vec<StreamInfo>)> {
+    let sniffed = sniff(html, base);
+    let (url, kind) = sniffed.best()?.clone();
+    tracing::info!(
+        "{}: found {} media, chose {}",
+        base,
+        sniffed.media.len(),
+        url
+    );
+    tracing::debug!("{}: found {:?}", base, sniffed.media);
+    // the extension of the url only tells if it is a known one
+    let suffix = Kind::of_url(&url)
+        .is_none()
+        .then(|| kind.suffix().to_string());
+    let title = sniffed
+        .title
+        .clone()
+        .or(base.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut meta = Meta::new(title);
+    if let Some(host) = base.host_str() {
+        meta = meta.field("uploader", host);
+    }
+    if let Some(date) = &sniffed.date {
+        meta = meta.field("date", date);
+    }
+    Ok((meta, vec![StreamInfo::new(url, suffix)]))
+}
+
+/// Downloads the media a page embeds, for sites without an extractor
+pub struct GenericPageTask {
+    id: Uuid,
+    url: Url,
+    addr: Addr<TaskActor>,
+    options: TaskOptions,
+}
+
+impl GenericPageTask {
+    fn new(id: Uuid, url: Url, options: TaskOptions) -> Self {
+        Self {
+            id,
+            url,
+            addr: TaskActor::new().start(),
+            options,
+        }
+    }
+}
+
+impl TaskExe for GenericPageTask {
+    type Info = StreamInfo;
+
+    async fn get_child_tasks(&self) -> TaskResult<(Meta, Vec<Self::Info>)> {
+        let resp = self.client()?.get(self.url.clone()).send().await?;
+        // relative links are of the page redirected to
+        let base = resp.url().clone();
+        page_infos(&resp.text().await?, &base)
+    }
+
+    /// Sends the origin of the page as `Referer`, which hotlink protection asks for
+    fn client(&self) -> TaskResult<reqwest::Client> {
+        Ok(crate::net::referer_client(SITE, &self.url)?)
+    }
+
+    /// The same `Referer` as the client
+    fn ffmpeg_input(&self) -> TaskResult<Vec<String>> {
+        let referer = crate::net::origin_referer(&self.url);
+        Ok(crate::net::ffmpeg_input(SITE, Some(referer))?)
+    }
+
+    fn addr(&self) -> &Addr<TaskActor> {
+        &self.addr
+    }
+
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn options(&self) -> &TaskOptions {
+        &self.options
+    }
+
+    fn site(&self) -> &str {
+        SITE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <title> Fallback title </title>
+  <meta property="og:title" content="A page">
+  <meta property="og:video" content="https://cdn.example.com/og.mp4">
+  <script type="application/ld+json">
+    {"@context": "https://schema.org", "@graph": [
+      {"@type": "VideoObject", "name": "ld", "contentUrl": "/ld/video.webm", "uploadDate": "2023-11-01T08:00:00+08:00"}
+    ]}
+  </script>
+  <script>var player = {"hls": "https:\/\/cdn.example.com\/live\/index.m3u8?t=1", "file": "https://cdn.example.com/s.mp4"};</script>
+</head>
+<body>
+  <video controls><source src="media/first.mp4" type="video/mp4"></video>
+  <audio src="/a/track"></audio>
+</body>
+</html>"#;
+
+    #[test]
+    fn sniff_test() {
+        let base = Url::parse("https://example.com/page/1").unwrap();
+        let sniffed = sniff(PAGE, &base);
+        assert_eq!(sniffed.title.as_deref(), Some("A page"));
+        assert_eq!(sniffed.date.as_deref(), Some("2023-11-01"));
+        let media = sniffed
+            .media
+            .iter()
+            .map(|(u, k)| (u.as_str(), *k))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            media,
+            [
+                ("https://cdn.example.com/og.mp4", Kind::Video),
+                ("https://example.com/ld/video.webm", Kind::Video),
+                ("https://example.com/page/media/first.mp4", Kind::Video),
+                ("https://example.com/a/track", Kind::Audio),
+                (
+                    "https://cdn.example.com/live/index.m3u8?t=1",
+                    Kind::Playlist
+                ),
+                ("https://cdn.example.com/s.mp4", Kind::Video),
+            ]
+        );
+        assert_eq!(
+            sniffed.best().unwrap().0.as_str(),
+            "https://cdn.example.com/og.mp4"
+        );
+    }
+
+    #[test]
+    fn og_test() {
+        let base = Url::parse("https://example.com/").unwrap();
+        let media = |html: &str| {
+            sniff(html, &base)
+                .media
+                .into_iter()
+                .map(|(u, k)| (u.to_string(), k))
+                .collect::<Vec<_>>()
+        };
+        // a player page, not a media
+        let player = r#"<meta property="og:video" content="https://example.com/embed/1">
+            <meta property="og:video:type" content="text/html">
+            <video src="/v.mp4"></video>"#;
+        assert_eq!(
+            media(player),
+            [("https://example.com/v.mp4".into(), Kind::Video)]
+        );
+        let untyped =
+            r#"<meta property="og:video" content="/embed/1"><video src="/v.mp4"></video>"#;
+        assert_eq!(
+            media(untyped),
+            [("https://example.com/v.mp4".into(), Kind::Video)]
+        );
+        let typed = r#"<meta property="og:video" content="/stream/1">
+            <meta property="og:video:secure_url" content="https://cdn.example.com/live">
+            <meta property="og:video:type" content="application/x-mpegURL">
+            <meta property="og:audio" content="/track?id=1">
+            <meta property="og:audio:type" content="audio/mpeg">"#;
+        assert_eq!(
+            media(typed),
+            [
+                ("https://cdn.example.com/live".into(), Kind::Playlist),
+                ("https://example.com/stream/1".into(), Kind::Playlist),
+                ("https://example.com/track?id=1".into(), Kind::Audio),
+            ]
+        );
+    }
+
+    #[test]
+    fn page_infos_test() {
+        let base = Url::parse("https://example.com/page/1").unwrap();
+        let (meta, infos) = page_infos(PAGE, &base).unwrap();
+        assert_eq!(meta.title, "A page");
+        assert_eq!(meta.fields["uploader"], "example.com");
+        assert_eq!(meta.fields["date"], "2023-11-01");
+        assert_eq!(
+            infos,
+            [StreamInfo::new(
+                Url::parse("https://cdn.example.com/og.mp4").unwrap(),
+                None
+            )]
+        );
+
+        let live =
+            r#"<meta property="og:video" content="/live/index.m3u8"><video src="/ad.mp4"></video>"#;
+        let (meta, infos) = page_infos(live, &base).unwrap();
+        assert_eq!(meta.title, "example.com");
+        assert_eq!(infos[0].url.as_str(), "https://example.com/live/index.m3u8");
+        assert!(infos[0].playlist);
+        assert_eq!(infos[0].suffix, "mp4");
+        let audio = r#"<audio src="/a/track.php?id=1"></audio>"#;
+        let (_, infos) = page_infos(audio, &base).unwrap();
+        assert_eq!(infos[0].suffix, "m4a");
+        assert!(!infos[0].playlist);
+        assert!(page_infos("<p>nothing</p>", &base).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn referer_test() {
+        let page = Url::parse("https://example.com/watch/1?t=2").unwrap();
+        let task = GenericPageTask::new(Uuid::new_v4(), page, TaskOptions::default());
+        let args = task.ffmpeg_input().unwrap();
+        let headers = args.iter().position(|a| a == "-headers").unwrap() + 1;
+        assert!(args[headers].contains("referer: https://example.com/\r\n"));
+        assert!(!args[headers].contains("/watch/1"));
+    }
+
+    #[test]
+    fn best_test() {
+        let base = Url::parse("https://example.com/").unwrap();
+        let audio = sniff(r#"<title>t</title><audio src="a.mp3"></audio>"#, &base);
+        assert_eq!(audio.title.as_deref(), Some("t"));
+        assert_eq!(
+            audio.best().unwrap().0.as_str(),
+            "https://example.com/a.mp3"
+        );
+        let playlist = sniff(r#"<video src="/live.m3u8"></video>"#, &base);
+        assert_eq!(
+            playlist.best().unwrap().0.as_str(),
+            "https://example.com/live.m3u8"
+        );
+        assert!(matches!(
+            sniff("<p>nothing</p>", &base).best(),
+            Err(crate::task::TaskError::StreamNotFound)
+        ));
+        assert!(GenericExtractor.matches(&base));
+        assert!(!GenericExtractor.matches(&Url::parse("ftp://example.com/a.mp4").unwrap()));
+    }
+}
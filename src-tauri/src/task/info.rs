@@ -1,12 +1,22 @@
 use url::Url;
 
+/// HLS and DASH, downloaded by ffmpeg into an mp4
+const PLAYLIST_EXTS: &[&str] = &["m3u8", "mpd"];
+
 pub trait Info: std::fmt::Debug {
     fn suffix(&self) -> String;
     fn url(&self) -> Url;
+
+    /// A playlist of segments, which ffmpeg downloads instead of ranged requests
+    fn playlist(&self) -> bool {
+        false
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct BiliInfo {
+    /// The quality id for video, e.g. 80 for 1080P
+    pub id: u32,
     #[serde(rename(deserialize = "base_url"))]
     pub url: Url,
     pub width: usize,
@@ -25,12 +35,57 @@ impl Info for BiliInfo {
     }
 }
 
-fn mime_suffix<S: AsRef<str>>(mime_type: S) -> String {
-    new_mime_guess::get_mime_extensions_str(mime_type.as_ref())
-        .unwrap()
+/// A stream found by an extractor which knows nothing more of it than its url
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    pub url: Url,
+    pub suffix: String,
+    pub playlist: bool,
+}
+
+impl StreamInfo {
+    /// The suffix is guessed from the path of `url` if not given, `mp4` if it has none.
+    /// A playlist is saved as `mp4`
+    pub fn new(url: Url, suffix: Option<String>) -> Self {
+        let suffix = suffix.filter(|s| !s.is_empty()).unwrap_or_else(|| {
+            std::path::Path::new(url.path())
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or("mp4".to_string())
+        });
+        match PLAYLIST_EXTS.contains(&suffix.as_str()) {
+            true => Self {
+                url,
+                suffix: "mp4".to_string(),
+                playlist: true,
+            },
+            false => Self {
+                url,
+                suffix,
+                playlist: false,
+            },
+        }
+    }
+}
+
+impl Info for StreamInfo {
+    fn suffix(&self) -> String {
+        self.suffix.to_owned()
+    }
+
+    fn url(&self) -> Url {
+        self.url.to_owned()
+    }
+
+    fn playlist(&self) -> bool {
+        self.playlist
+    }
+}
+
+fn mime_suffix<S: AsRef<str>>(mime_type: S) -> Option<String> {
+    new_mime_guess::get_mime_extensions_str(mime_type.as_ref())?
         .first()
-        .unwrap()
-        .to_string()
+        .map(|s| s.to_string())
 }
 
 fn from_mime<'de, D>(deserializer: D) -> Result<String, D::Error>
@@ -38,5 +93,25 @@ where
     D: serde::Deserializer<'de>,
 {
     let s: String = serde::Deserialize::deserialize(deserializer)?;
-    Ok(mime_suffix(s))
+    mime_suffix(&s).ok_or_else(|| serde::de::Error::custom(format!("unknown mime type {s}")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_mime_test() {
+        let info = |mime: &str| {
+            serde_json::from_value::<BiliInfo>(serde_json::json!({
+                "id": 80,
+                "base_url": "https://example.com/video",
+                "width": 1920,
+                "height": 1080,
+                "mime_type": mime,
+            }))
+        };
+        assert!(info("video/mp4").is_ok());
+        assert!(info("video/x-unknown-format").is_err());
+    }
 }
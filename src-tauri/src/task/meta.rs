@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+
+/// What an extractor knows about the media.
+/// `fields` fill the `{field}`s of `filename_template` in config.
+#[derive(Debug, Default, Clone)]
+pub struct Meta {
+    pub title: String,
+    pub fields: HashMap<&'static str, String>,
+}
+
+impl Meta {
+    pub fn new<S: AsRef<str>>(title: S) -> Self {
+        let title = title.as_ref().to_string();
+        Self {
+            fields: HashMap::from([("title", title.clone())]),
+            title,
+        }
+    }
+
+    pub fn field<S: AsRef<str>>(mut self, key: &'static str, value: S) -> Self {
+        self.fields.insert(key, value.as_ref().to_string());
+        self
+    }
+}
@@ -1,29 +1,38 @@
 mod bilibili;
 mod error;
+mod extractor;
+mod generic;
 mod info;
-mod parser;
+mod meta;
+mod options;
 mod pixiv;
+mod script;
 mod task_actor;
 
-use crate::{config::get_config, utils::TempDirHandler};
+use crate::{
+    config::get_config,
+    net::cookie_client,
+    utils::{from_spec, TempDirHandler},
+};
 use actix::Addr;
+pub use bilibili::Account;
 pub use error::*;
+pub use extractor::{list as list_extractors, ExtractorInfo};
 pub use info::Info;
+pub use meta::Meta;
+pub use options::TaskOptions;
+pub use script::load_scripts;
 use snafu::OptionExt;
-use std::sync::Arc;
-use task_actor::{Cancel, Continue_, Pause, RunTask, SetFilename, TaskActor};
+use std::{future::Future, pin::Pin, sync::Arc};
+use task_actor::{
+    get_total, Cancel, Continue_, Merge, Pause, RunPlaylist, RunTask, SetFailed, SetFilename,
+    SetRateLimit, TaskActor,
+};
 use tokio::sync::oneshot;
 use url::Url;
 use uuid::Uuid;
 
-use self::task_actor::ProgressQuery;
-
-#[derive(Debug)]
-#[cfg_attr(test, derive(PartialEq))]
-pub enum TaskType {
-    BiliBili,
-    Unknown,
-}
+use self::task_actor::{Progress, ProgressQuery};
 
 macro_rules! task_func {
     (($func: ident, $msg: ident)) => {
@@ -40,79 +49,200 @@ macro_rules! task_func {
 pub trait TaskExe {
     type Info: Info;
 
-    // Return (filename, infos)
-    // filename: the filename of the video
+    // Return (meta, infos)
+    // meta: the title of the video and the fields for the filename template
     // infos: the video and audio infos which impl the Info trait
-    async fn get_child_tasks(&self) -> TaskResult<(String, Vec<Self::Info>)>;
+    async fn get_child_tasks(&self) -> TaskResult<(Meta, Vec<Self::Info>)>;
 
     fn addr(&self) -> &Addr<TaskActor>;
     fn url(&self) -> &Url;
     fn id(&self) -> &Uuid;
+    fn options(&self) -> &TaskOptions;
+    /// The config prefix of the site, e.g. `bili` for `bili_cookie` and `bili_headers`
+    fn site(&self) -> &str;
 
     async fn go(&self) -> TaskResult<()> {
-        let (filename, infos) = self.get_child_tasks().await?;
-        self.save(filename, infos).await?;
-        Ok(())
+        let ret = match self.get_child_tasks().await {
+            Ok((meta, infos)) => self.save(meta, infos).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &ret {
+            tracing::error!("{}: {}", self.url(), e);
+            self.addr().do_send(SetFailed(e.to_string()));
+        }
+        ret
     }
 
-    async fn save(&self, filename: impl AsRef<str>, infos: Vec<Self::Info>) -> TaskResult<()> {
-        let temp_dir = Arc::new(TempDirHandler::new(filename.as_ref()).unwrap());
-        self.addr()
-            .send(SetFilename(filename.as_ref().to_string()))
-            .await??;
-        let referer = self.referer()?;
+    async fn save(&self, meta: Meta, infos: Vec<Self::Info>) -> TaskResult<()> {
+        let options = self.options();
+        let temp_dir = Arc::new(TempDirHandler::new(
+            self.id(),
+            &meta.title,
+            meta.fields,
+            options.save_dir.as_deref(),
+            options.filename.as_deref(),
+        )?);
+        self.addr().send(SetFilename(meta.title)).await??;
+        self.addr().send(SetRateLimit(options.rate_limit)).await??;
+        let mut post_process = vec![];
+        for spec in options.post_process.iter().flatten() {
+            post_process.push(from_spec(spec).context(task_error::UnknownPostProcessor {
+                spec: spec.as_str(),
+            })?);
+        }
+        let client = Arc::new(self.client()?);
+        let mut totals = vec![];
+        for info in infos.iter() {
+            totals.push(match info.playlist() {
+                true => None,
+                false => get_total(client.clone(), info.url()).await,
+            });
+        }
+        match totals.iter().copied().sum::<Option<usize>>() {
+            Some(expected) => temp_dir.preflight(expected as u64)?,
+            None => tracing::warn!("{}: size unknown, free space not checked", self.url()),
+        }
         let mut rxs = vec![];
-        for info in infos.into_iter() {
+        for (info, total) in infos.into_iter().zip(totals) {
             let (tx, rx) = tokio::sync::oneshot::channel();
-            let run_task = RunTask::new(info.suffix(), info.url(), &referer, temp_dir.clone(), tx);
+            if info.playlist() {
+                let input = self.ffmpeg_input()?;
+                let run_playlist =
+                    RunPlaylist::new(info.suffix(), info.url(), input, temp_dir.clone(), tx);
+                self.addr().send(run_playlist).await??;
+                rxs.push(rx);
+                continue;
+            }
+            let run_task = RunTask::new(
+                info.suffix(),
+                info.url(),
+                total,
+                (*client).clone(),
+                temp_dir.clone(),
+                tx,
+            );
             self.addr().send(run_task).await??;
             rxs.push(rx);
         }
         for rx in rxs {
-            rx.await.unwrap()?;
+            if let Err(e) = rx.await.unwrap_or_else(|_| actor_error::Stopped.fail()) {
+                if matches!(e, ActorError::Cancelled) {
+                    temp_dir.cleanup();
+                }
+                return Err(e.into());
+            }
+        }
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.addr()
+            .send(Merge::new(
+                temp_dir.clone(),
+                options.mode.unwrap_or_default(),
+                options.audio_format,
+                post_process,
+                tx,
+            ))
+            .await??;
+        match rx.await.unwrap_or_else(|_| actor_error::Stopped.fail()) {
+            Ok(()) => temp_dir.cleanup(),
+            Err(ActorError::Cancelled) => {
+                temp_dir.cleanup();
+                return Err(ActorError::Cancelled.into());
+            }
+            // keep the parts, so merging can be retried
+            Err(e) => return Err(e.into()),
         }
-        temp_dir.save();
         Ok(())
     }
 
-    fn progress_query(&self) -> TaskResult<(String, usize, usize, String)> {
+    fn progress_query(&self) -> TaskResult<Progress> {
         let (tx, rx) = oneshot::channel();
         self.addr().do_send(ProgressQuery::new(tx));
-        Ok(rx.blocking_recv().unwrap().unwrap())
-    }
-
-    fn referer(&self) -> TaskResult<String> {
-        match self.task_type() {
-            TaskType::BiliBili => Ok("https://www.bilibili.com/".to_string()),
-            TaskType::Unknown => task_error::UnknownTaskType.fail()?,
-        }
+        Ok(rx
+            .blocking_recv()
+            .unwrap_or_else(|_| actor_error::Stopped.fail())?)
     }
 
     fn cookie(&self) -> TaskResult<String> {
-        match self.task_type() {
-            TaskType::BiliBili => get_config("bili_cookie").context(task_error::ConfigNotFound),
-            TaskType::Unknown => task_error::UnknownTaskType.fail()?,
-        }
+        get_config(cookie_key(self.site(), self.options())).context(task_error::ConfigNotFound)
     }
 
-    fn user_agent(&self) -> TaskResult<String> {
-        get_config("user-agent").context(task_error::ConfigNotFound)
+    /// The shared client of the task's cookie profile, which sends and updates its cookies
+    /// along with the headers of the site
+    fn client(&self) -> TaskResult<reqwest::Client> {
+        let site = self.site();
+        Ok(cookie_client(
+            site,
+            cookie_profile(site, self.options()).as_deref(),
+        )?)
     }
 
-    fn task_type(&self) -> TaskType {
-        match self.url().host_str() {
-            Some("bilibili.com") | Some("www.bilibili.com") => TaskType::BiliBili,
-            Some(_) | None => TaskType::Unknown,
-        }
+    /// What ffmpeg sends for the playlists of the task, the headers of the site
+    fn ffmpeg_input(&self) -> TaskResult<Vec<String>> {
+        Ok(crate::net::ffmpeg_input(self.site(), None)?)
     }
 
     task_func![(cancel, Cancel), (pause, Pause), (continue_, Continue_)];
 }
 
-pub fn new_task<S: AsRef<str>>(url: S) -> TaskResult<impl TaskExe<Info = impl Info>> {
-    let url = url.as_ref().parse::<Url>()?;
-    match url.host_str() {
-        Some("bilibili.com") | Some("www.bilibili.com") => bilibili::BiliTask::new(url),
-        Some(_) | None => task_error::UnknownTaskType.fail()?,
+/// A [`TaskExe`] of any site, so the tasks of different sites can be kept together
+pub trait DynTask: Send + Sync {
+    fn go(&self) -> Pin<Box<dyn Future<Output = TaskResult<()>> + '_>>;
+    fn id(&self) -> &Uuid;
+    fn progress_query(&self) -> TaskResult<Progress>;
+    fn cancel(&self) -> TaskResult<()>;
+    fn pause(&self) -> TaskResult<()>;
+    fn continue_(&self) -> TaskResult<()>;
+}
+
+impl<T> DynTask for T
+where
+    T: TaskExe + Send + Sync,
+{
+    fn go(&self) -> Pin<Box<dyn Future<Output = TaskResult<()>> + '_>> {
+        Box::pin(TaskExe::go(self))
+    }
+
+    fn id(&self) -> &Uuid {
+        TaskExe::id(self)
+    }
+
+    fn progress_query(&self) -> TaskResult<Progress> {
+        TaskExe::progress_query(self)
+    }
+
+    fn cancel(&self) -> TaskResult<()> {
+        TaskExe::cancel(self)
+    }
+
+    fn pause(&self) -> TaskResult<()> {
+        TaskExe::pause(self)
     }
+
+    fn continue_(&self) -> TaskResult<()> {
+        TaskExe::continue_(self)
+    }
+}
+
+/// The cookie profile of `site` chosen by the task, or by `{site}_profile` in config
+fn cookie_profile(site: &str, options: &TaskOptions) -> Option<String> {
+    options
+        .cookie_profile
+        .clone()
+        .or_else(|| get_config(format!("{site}_profile")))
+}
+
+/// The config key of the cookie of `site` for the profile chosen by the task
+fn cookie_key(site: &str, options: &TaskOptions) -> String {
+    crate::config::cookie_key(site, cookie_profile(site, options).as_deref())
+}
+
+/// A task of the extractor matching `url`, `id` is a new one unless the task is restored
+pub fn new_task<S: AsRef<str>>(
+    id: Uuid,
+    url: S,
+    options: TaskOptions,
+) -> TaskResult<Arc<dyn DynTask>> {
+    let url = url.as_ref().parse::<Url>()?;
+    let extractor = extractor::find(&url).context(task_error::UnknownTaskType)?;
+    extractor.extract(id, url, options.with_defaults())
 }
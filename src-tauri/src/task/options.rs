@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+
+use crate::{
+    config::app_config,
+    utils::{AudioFormat, DownloadMode},
+};
+
+/// Per-task options given at creation, the unset ones fall back to config
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TaskOptions {
+    pub save_dir: Option<PathBuf>,
+    /// A filename template used instead of `filename_template` in config
+    pub filename: Option<String>,
+    /// The preferred quality id, the best one not above it is chosen
+    pub quality: Option<u32>,
+    pub cookie_profile: Option<String>,
+    /// Bytes per second, shared by all the streams of the task
+    pub rate_limit: Option<u64>,
+    /// Names of the post processors to run after merging, in order
+    pub post_process: Option<Vec<String>>,
+    /// Download both the video and audio, or only one of them
+    pub mode: Option<DownloadMode>,
+    /// Transcode audio only downloads to this format, kept as m4a if unset
+    pub audio_format: Option<AudioFormat>,
+}
+
+impl TaskOptions {
+    pub fn with_defaults(self) -> Self {
+        let config = app_config().unwrap_or_default();
+        Self {
+            save_dir: self
+                .save_dir
+                .or(Some(config.save_dir).filter(|d| !d.as_os_str().is_empty())),
+            filename: self
+                .filename
+                .or(Some(config.filename_template))
+                .filter(|f| !f.is_empty()),
+            quality: self.quality.or(Some(config.quality)),
+            // resolved by the site of the task, see `cookie_key`
+            cookie_profile: self.cookie_profile,
+            rate_limit: self
+                .rate_limit
+                .or(Some(config.rate_limit))
+                .filter(|r| *r > 0),
+            post_process: self.post_process.or_else(|| {
+                Some(
+                    config
+                        .post_process
+                        .split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect(),
+                )
+            }),
+            mode: self.mode.or(Some(config.download_mode)),
+            audio_format: self
+                .audio_format
+                .or_else(|| config.audio_format.parse().ok()),
+        }
+    }
+}
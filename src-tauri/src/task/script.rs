@@ -0,0 +1,431 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use actix::{Actor, Addr};
+use regex::Regex;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST};
+use serde::Deserialize;
+use tokio::runtime::Handle;
+use url::Url;
+use uuid::Uuid;
+
+use super::{
+    error::{task_error, TaskError, TaskResult},
+    extractor::{set_scripted, Extractor},
+    info::StreamInfo,
+    task_actor::TaskActor,
+    DynTask, Meta, TaskExe, TaskOptions,
+};
+
+/// The folder of the data dir scripts are loaded from
+const SCRIPT_DIR: &str = "extractors";
+/// Stops a script looping forever
+const MAX_OPERATIONS: u64 = 10_000_000;
+/// Requests one run of `extract` may send
+const MAX_REQUESTS: usize = 64;
+/// How long one run may take, its requests included
+const TIME_LIMIT: Duration = Duration::from_secs(60);
+
+/// What `extract` of a script returns
+#[derive(Debug, Deserialize)]
+struct Extracted {
+    title: String,
+    id: Option<String>,
+    uploader: Option<String>,
+    /// `YYYY-MM-DD`
+    date: Option<String>,
+    streams: Vec<Stream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Stream {
+    /// May be relative to the url of the page
+    url: String,
+    suffix: Option<String>,
+}
+
+/// An extractor written as a rhai script, e.g.
+/// ```rhai
+/// const NAME = "example";
+/// const PATTERNS = ["example.com/watch/"];
+///
+/// fn extract(url) {
+///     let page = get(url);
+///     #{ title: find(page, "<title>(.*?)</title>"), streams: [#{ url: find(page, "src=\"(.*?\\.mp4)\"") }] }
+/// }
+/// ```
+/// `NAME` is the name of the file if not set, and the config prefix of its requests,
+/// e.g. `example_headers`.
+#[derive(Clone)]
+pub struct ScriptExtractor {
+    name: String,
+    patterns: Vec<String>,
+    /// Shared with the tasks, so a reload does not change a running one
+    ast: Arc<AST>,
+}
+
+impl ScriptExtractor {
+    pub fn load(path: &Path) -> TaskResult<Self> {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let fail = |message: String| {
+            task_error::ScriptError {
+                name: stem.to_string(),
+                message,
+            }
+            .build()
+        };
+        let engine = engine(None);
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| fail(e.to_string()))?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| fail(e.to_string()))?;
+        let patterns = scope
+            .get_value::<Array>("PATTERNS")
+            .ok_or_else(|| fail("PATTERNS is not an array".to_string()))?
+            .into_iter()
+            .map(|p| {
+                p.into_string()
+                    .map_err(|t| fail(format!("a pattern is {t}")))
+            })
+            .collect::<TaskResult<Vec<_>>>()?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "extract" && f.params.len() == 1)
+        {
+            return Err(fail("fn extract(url) is missing".to_string()));
+        }
+        Ok(Self {
+            name: scope
+                .get_value::<String>("NAME")
+                .unwrap_or(stem.to_string()),
+            patterns,
+            ast: Arc::new(ast),
+        })
+    }
+
+    /// Runs the script, which may block on its requests
+    fn run(&self, url: &Url, client: reqwest::Client, handle: Handle) -> TaskResult<Extracted> {
+        let fail = |message: String| {
+            task_error::ScriptError {
+                name: self.name.as_str(),
+                message,
+            }
+            .build()
+        };
+        let engine = engine(Some((client, handle)));
+        let result = engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, "extract", (url.to_string(),))
+            .map_err(|e| fail(e.to_string()))?;
+        rhai::serde::from_dynamic(&result).map_err(|e| fail(e.to_string()))
+    }
+}
+
+impl Extractor for ScriptExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn patterns(&self) -> Vec<String> {
+        self.patterns.clone()
+    }
+
+    fn extract(&self, id: Uuid, url: Url, options: TaskOptions) -> TaskResult<Arc<dyn DynTask>> {
+        let script = Arc::new(self.clone());
+        Ok(Arc::new(ScriptTask::new(id, url, options, script)))
+    }
+}
+
+/// Load the scripts in `<data dir>/extractors`, replacing the ones loaded before.
+/// A script which fails to load is skipped, its error returned.
+pub fn load_scripts() -> TaskResult<Vec<TaskError>> {
+    let dir = crate::config::data_dir()?.join(SCRIPT_DIR);
+    let mut paths = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "rhai"))
+            .collect::<Vec<_>>(),
+        Err(_) => vec![],
+    };
+    paths.sort();
+    let (mut loaded, mut errors) = (vec![], vec![]);
+    for path in paths {
+        match ScriptExtractor::load(&path) {
+            Ok(script) => {
+                tracing::info!("extractor {} loaded from {:?}", script.name, path);
+                loaded.push(Arc::new(script) as Arc<dyn Extractor>);
+            }
+            Err(e) => {
+                tracing::warn!("{}", e);
+                errors.push(e);
+            }
+        }
+    }
+    set_scripted(loaded);
+    Ok(errors)
+}
+
+/// `get(url)` and `get_json(url)` send requests with `client`, blocking on `handle`.
+/// `find(text, pattern)` and `find_all(text, pattern)` match a regex,
+/// giving its first group, or the whole match if it has none.
+/// The engine stops after [`TIME_LIMIT`] or [`MAX_REQUESTS`].
+fn engine(client: Option<(reqwest::Client, Handle)>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let deadline = Instant::now() + TIME_LIMIT;
+    engine.on_progress(move |_| {
+        (Instant::now() > deadline).then(|| format!("ran over {TIME_LIMIT:?}").into())
+    });
+    let requests = AtomicUsize::new(0);
+    let get = move |url: &str| -> Result<String, Box<EvalAltResult>> {
+        let (client, handle) = client
+            .as_ref()
+            .ok_or("requests are only allowed in extract")?;
+        if requests.fetch_add(1, Ordering::Relaxed) >= MAX_REQUESTS {
+            return Err(format!("sent over {MAX_REQUESTS} requests").into());
+        }
+        let left = deadline
+            .checked_duration_since(Instant::now())
+            .ok_or(format!("ran over {TIME_LIMIT:?}"))?;
+        let text =
+            handle.block_on(async { client.get(url).timeout(left).send().await?.text().await });
+        text.map_err(|e| e.to_string().into())
+    };
+    let get = Arc::new(get);
+    let get_text = get.clone();
+    engine.register_fn("get", move |url: &str| get_text(url));
+    engine.register_fn(
+        "get_json",
+        move |url: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            let json =
+                serde_json::from_str::<serde_json::Value>(&get(url)?).map_err(|e| e.to_string())?;
+            rhai::serde::to_dynamic(json)
+        },
+    );
+    engine.register_fn(
+        "find",
+        |text: &str, pattern: &str| -> Result<String, Box<EvalAltResult>> {
+            Ok(find_all(text, pattern)?
+                .into_iter()
+                .next()
+                .unwrap_or_default())
+        },
+    );
+    engine.register_fn(
+        "find_all",
+        |text: &str, pattern: &str| -> Result<Array, Box<EvalAltResult>> {
+            Ok(find_all(text, pattern)?
+                .into_iter()
+                .map(Dynamic::from)
+                .collect())
+        },
+    );
+    engine
+}
+
+fn find_all(text: &str, pattern: &str) -> Result<Vec<String>, Box<EvalAltResult>> {
+    let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+    Ok(regex
+        .captures_iter(text)
+        .filter_map(|c| c.get(1).or(c.get(0)))
+        .map(|m| m.as_str().to_string())
+        .collect())
+}
+
+pub struct ScriptTask {
+    id: Uuid,
+    url: Url,
+    addr: Addr<TaskActor>,
+    options: TaskOptions,
+    script: Arc<ScriptExtractor>,
+}
+
+impl ScriptTask {
+    fn new(id: Uuid, url: Url, options: TaskOptions, script: Arc<ScriptExtractor>) -> Self {
+        Self {
+            id,
+            url,
+            addr: TaskActor::new().start(),
+            options,
+            script,
+        }
+    }
+}
+
+impl TaskExe for ScriptTask {
+    type Info = StreamInfo;
+
+    async fn get_child_tasks(&self) -> TaskResult<(Meta, Vec<Self::Info>)> {
+        let (script, url) = (self.script.clone(), self.url.clone());
+        let (client, handle) = (self.client()?, Handle::current());
+        let extracted =
+            match tokio::task::spawn_blocking(move || script.run(&url, client, handle)).await {
+                Ok(extracted) => extracted?,
+                Err(e) => task_error::ScriptError {
+                    name: self.script.name.as_str(),
+                    message: e.to_string(),
+                }
+                .fail()?,
+            };
+        let mut meta = Meta::new(extracted.title);
+        for (key, value) in [
+            ("id", extracted.id),
+            ("uploader", extracted.uploader),
+            ("date", extracted.date),
+        ] {
+            if let Some(value) = value {
+                meta = meta.field(key, value);
+            }
+        }
+        let infos = extracted
+            .streams
+            .into_iter()
+            .map(|s| Ok(StreamInfo::new(self.url.join(&s.url)?, s.suffix)))
+            .collect::<TaskResult<Vec<_>>>()?;
+        if infos.is_empty() {
+            task_error::StreamNotFound.fail()?;
+        }
+        Ok((meta, infos))
+    }
+
+    /// Without cookies, the site has no cookie profiles.
+    /// A `Cookie` can be sent with `{name}_headers` instead
+    fn client(&self) -> TaskResult<reqwest::Client> {
+        Ok(crate::net::client(Some(self.site()))?)
+    }
+
+    fn addr(&self) -> &Addr<TaskActor> {
+        &self.addr
+    }
+
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn options(&self) -> &TaskOptions {
+        &self.options
+    }
+
+    fn site(&self) -> &str {
+        &self.script.name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        const NAME = "example";
+        const PATTERNS = ["example.com/watch/", "*.example.org"];
+
+        fn extract(url) {
+            let page = `<title>A video</title><source src="/media/v.webm"><a href="https://cdn.example.com/a.m4a?x=1">`;
+            let streams = [#{ url: find(page, "src=\"(.*?)\"") }];
+            for link in find_all(page, "https://[^\"]+") {
+                streams.push(#{ url: link, suffix: "m4a" });
+            }
+            #{ title: find(page, "<title>(.*?)</title>"), uploader: "me", streams: streams }
+        }
+    "#;
+
+    fn script() -> ScriptExtractor {
+        let dir = tempdir::TempDir::new("script_test").unwrap();
+        let path = dir.path().join("example.rhai");
+        std::fs::write(&path, SCRIPT).unwrap();
+        ScriptExtractor::load(&path).unwrap()
+    }
+
+    #[test]
+    fn load_test() {
+        let script = script();
+        assert_eq!(script.name(), "example");
+        assert!(script.matches(&Url::parse("https://example.com/watch/1").unwrap()));
+        assert!(script.matches(&Url::parse("https://www.example.org/").unwrap()));
+        assert!(!script.matches(&Url::parse("https://example.com/").unwrap()));
+
+        let dir = tempdir::TempDir::new("script_test").unwrap();
+        let path = dir.path().join("broken.rhai");
+        std::fs::write(&path, "const PATTERNS = [\"a.com\"];").unwrap();
+        assert!(matches!(
+            ScriptExtractor::load(&path),
+            Err(TaskError::Script { name, .. }) if name == "broken"
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn run_test() {
+        let script = script();
+        let url = Url::parse("https://example.com/watch/1").unwrap();
+        let client = crate::net::client(None).unwrap();
+        let handle = Handle::current();
+        let extracted = tokio::task::spawn_blocking(move || script.run(&url, client, handle))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(extracted.title, "A video");
+        assert_eq!(extracted.uploader.as_deref(), Some("me"));
+        let page = Url::parse("https://example.com/watch/1").unwrap();
+        let infos = extracted
+            .streams
+            .into_iter()
+            .map(|s| StreamInfo::new(page.join(&s.url).unwrap(), s.suffix))
+            .collect::<Vec<_>>();
+        assert_eq!(infos[0].url.as_str(), "https://example.com/media/v.webm");
+        assert_eq!(infos[0].suffix, "webm");
+        assert_eq!(infos[1].suffix, "m4a");
+    }
+
+    #[actix_rt::test]
+    async fn limit_test() {
+        let dir = tempdir::TempDir::new("script_test").unwrap();
+        let path = dir.path().join("greedy.rhai");
+        std::fs::write(
+            &path,
+            r#"
+            const PATTERNS = ["greedy.example"];
+            fn extract(url) {
+                let last = "";
+                for i in 0..100 {
+                    try { get(url); } catch (e) { last = `${e}`; }
+                }
+                #{ title: last, streams: [] }
+            }
+        "#,
+        )
+        .unwrap();
+        let script = ScriptExtractor::load(&path).unwrap();
+        drop(dir);
+        // the loaded script is used, the file is gone
+        let url = Url::parse("http://127.0.0.1:9/").unwrap();
+        assert!(script
+            .extract(Uuid::new_v4(), url.clone(), TaskOptions::default())
+            .is_ok());
+        let client = crate::net::client(None).unwrap();
+        let handle = Handle::current();
+        let extracted = tokio::task::spawn_blocking(move || script.run(&url, client, handle))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(extracted.title.contains("requests"), "{}", extracted.title);
+    }
+
+    #[test]
+    fn find_test() {
+        assert_eq!(find_all("a1 b2", r"[a-z](\d)").unwrap(), ["1", "2"]);
+        assert_eq!(find_all("a1 b2", r"[a-z]\d").unwrap(), ["a1", "b2"]);
+        assert!(find_all("", "(").is_err());
+    }
+}
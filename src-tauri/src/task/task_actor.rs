@@ -1,15 +1,24 @@
-use super::error::{actor_error, ActorResult};
-use crate::utils::TempDirHandler;
+use super::error::{actor_error, ActorError, ActorResult};
+use crate::utils::{
+    run_chain, AudioFormat, DownloadMode, PostProcessor, TemDirError, TempDirHandler,
+};
 
 use actix::prelude::*;
 use num_enum::{FromPrimitive, IntoPrimitive};
 use reqwest::Client;
+use std::path::Path;
 use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 use tracing::{instrument, Level};
 use url::Url;
 
+/// The warning of a task paused for disk space
+const LOW_SPACE: &str = "Paused, disk space is running low. Free some space and continue";
+/// Failed requests of a range in a row before the task fails
+const MAX_RETRIES: u32 = 5;
+
 #[derive(Eq, PartialEq, FromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 enum Instrument {
@@ -19,6 +28,9 @@ enum Instrument {
     Paused,
     Cancel,
     Finish,
+    Merge,
+    Fail,
+    Process,
 }
 
 #[derive(Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
@@ -30,6 +42,9 @@ enum State {
     Paused,
     Cancelled,
     Finished,
+    Merging,
+    Failed,
+    Processing,
 }
 
 #[derive(Default)]
@@ -53,12 +68,46 @@ impl TaskState {
     }
 }
 
+/// Paces the streams of a task to `limit` bytes per second in total
+struct RateLimiter {
+    limit: u64,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            // time spent paused should not be made up for with a burst
+            *next = (*next).max(now) + Duration::from_secs_f64(bytes as f64 / self.limit as f64);
+            next.saturating_duration_since(now)
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
 // region TaskActor
 pub struct TaskActor {
     state: Arc<TaskState>,
     total: Arc<AtomicUsize>,
     finished: Arc<AtomicUsize>,
     filename: Option<String>,
+    limiter: Option<Arc<RateLimiter>>,
+    stage: Arc<Mutex<Option<String>>>,
+    /// Something the user should know, e.g. a lower quality than asked for
+    warning: Option<String>,
+    /// Checked again for free space when the user continues
+    temp_dir: Option<Arc<TempDirHandler>>,
 }
 
 impl TaskActor {
@@ -68,6 +117,10 @@ impl TaskActor {
             total: Arc::new(AtomicUsize::new(0)),
             finished: Arc::new(AtomicUsize::new(0)),
             filename: None,
+            limiter: None,
+            stage: Arc::new(Mutex::new(None)),
+            warning: None,
+            temp_dir: None,
         }
     }
 }
@@ -86,27 +139,32 @@ impl Actor for TaskActor {
 pub struct RunTask {
     suffix: String,
     url: Url,
-    referer: String,
+    /// Measured before the preflight, asked again if unknown
+    total: Option<usize>,
+    /// Shared with the other tasks of the site, so connections are reused.
+    /// It sends the headers of the site, e.g. its `Referer`
+    client: Client,
     temp_dir: Arc<TempDirHandler>,
     tx: oneshot::Sender<ActorResult<()>>,
 }
 
 impl RunTask {
-    pub fn new<S1, S2>(
-        suffix: S1,
+    pub fn new<S>(
+        suffix: S,
         url: Url,
-        referer: S2,
+        total: Option<usize>,
+        client: Client,
         temp_dir: Arc<TempDirHandler>,
         tx: oneshot::Sender<ActorResult<()>>,
     ) -> Self
     where
-        S1: AsRef<str>,
-        S2: AsRef<str>,
+        S: AsRef<str>,
     {
         Self {
             suffix: suffix.as_ref().to_string(),
             url,
-            referer: referer.as_ref().to_string(),
+            total,
+            client,
             temp_dir,
             tx,
         }
@@ -116,29 +174,47 @@ impl RunTask {
 impl Handler<RunTask> for TaskActor {
     type Result = ActorResult<()>;
 
-    #[instrument(level=Level::DEBUG, skip(self, msg, _ctx), fields(url=msg.url.as_str(), format=msg.suffix), err)]
-    fn handle(&mut self, msg: RunTask, _ctx: &mut Self::Context) -> Self::Result {
+    #[instrument(level=Level::DEBUG, skip(self, msg, ctx), fields(url=msg.url.as_str(), format=msg.suffix), err)]
+    fn handle(&mut self, msg: RunTask, ctx: &mut Self::Context) -> Self::Result {
+        self.temp_dir = Some(msg.temp_dir.clone());
+        let addr = ctx.address();
         let actor_total = self.total.clone();
         let actor_finished = self.finished.clone();
         let state = self.state.clone();
+        let limiter = self.limiter.clone();
+        let read_timeout = crate::net::read_timeout();
         actix_rt::spawn(async move {
-            let client = Arc::new(
-                reqwest::Client::builder()
-                    .user_agent(crate::config::get_config("user-agent").unwrap())
-                    .build()
-                    .unwrap(),
-            );
-            let total = get_total(client.clone(), msg.url.clone(), &msg.referer)
-                .await
-                .unwrap_or(0);
+            let client = Arc::new(msg.client.clone());
+            let total = match msg.total {
+                Some(total) => Some(total),
+                None => get_total(client.clone(), msg.url.clone()).await,
+            };
+            let Some(total) = total else {
+                state.trans(Instrument::Fail);
+                msg.tx.send(actor_error::ContentLengthUnknown.fail()).ok();
+                return;
+            };
             actor_total.fetch_add(total, Ordering::Relaxed);
+            let writer = match msg.temp_dir.writer(&msg.suffix, total as u64) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    state.trans(Instrument::Fail);
+                    msg.tx.send(Err(e.into())).ok();
+                    return;
+                }
+            };
             let mut finished: usize = 0;
+            let mut retries = 0;
             while finished < total {
                 match state.now() {
+                    State::Downloading if msg.temp_dir.low_space() => {
+                        tracing::warn!("disk space is running low, pausing");
+                        addr.do_send(SetWarning(Some(LOW_SPACE.to_string())));
+                        state.trans(Instrument::TryPause);
+                    }
                     State::Downloading => {
-                        let mut resp = client
+                        let resp = client
                             .get(msg.url.clone())
-                            .header("Referer", &msg.referer)
                             .header(
                                 "Range",
                                 format!(
@@ -149,12 +225,63 @@ impl Handler<RunTask> for TaskActor {
                             )
                             .send()
                             .await
-                            .unwrap();
-                        while let Some(c) = resp.chunk().await.unwrap() {
-                            msg.temp_dir.write(&msg.suffix, &c).unwrap();
-                            actor_finished.fetch_add(c.len(), Ordering::Relaxed);
+                            .and_then(|resp| resp.error_for_status());
+                        let mut offset = finished as u64;
+                        let mut stalled = false;
+                        let mut failed = None;
+                        match resp {
+                            Ok(mut resp) => loop {
+                                let c = match tokio::time::timeout(read_timeout, resp.chunk()).await
+                                {
+                                    Ok(Ok(Some(c))) => c,
+                                    Ok(Ok(None)) => break,
+                                    Ok(Err(e)) => {
+                                        failed = Some(e);
+                                        break;
+                                    }
+                                    Err(_) => {
+                                        stalled = true;
+                                        break;
+                                    }
+                                };
+                                if let Err(e) = writer.write_at(offset, &c) {
+                                    state.trans(Instrument::Fail);
+                                    msg.tx.send(Err(e.into())).ok();
+                                    return;
+                                }
+                                offset += c.len() as u64;
+                                actor_finished.fetch_add(c.len(), Ordering::Relaxed);
+                                if let Some(limiter) = limiter.as_ref() {
+                                    limiter.consume(c.len()).await;
+                                }
+                            },
+                            Err(e) => failed = Some(e),
+                        }
+                        if let Some(e) = failed {
+                            // a range that moved on starts counting again
+                            if offset > finished as u64 {
+                                retries = 0;
+                            }
+                            retries += 1;
+                            if retries > MAX_RETRIES {
+                                tracing::error!("request failed {} times: {}", retries, e);
+                                state.trans(Instrument::Fail);
+                                msg.tx
+                                    .send(Err(ActorError::NetWorkError { source: e }))
+                                    .ok();
+                                return;
+                            }
+                            tracing::warn!("request failed: {}, requesting again", e);
+                            finished = offset as usize;
+                            tokio::time::sleep(Duration::from_secs(retries as u64)).await;
+                        } else if stalled {
+                            // asked again from where it stopped
+                            tracing::warn!("no data for {:?}, requesting again", read_timeout);
+                            finished = offset as usize;
+                        } else {
+                            retries = 0;
+                            finished += (1 << 23) + 1;
                         }
-                        finished += (1 << 23) + 1;
                     }
                     State::Cancelled => break,
                     _ => {
@@ -163,13 +290,15 @@ impl Handler<RunTask> for TaskActor {
                     }
                 }
             }
+            if let Err(e) = writer.flush() {
+                state.trans(Instrument::Fail);
+                msg.tx.send(Err(e.into())).ok();
+                return;
+            }
             if state.now() == State::Cancelled {
-                msg.tx.send(actor_error::Cancelled.fail()).unwrap();
+                msg.tx.send(actor_error::Cancelled.fail()).ok();
             } else {
-                if actor_finished.load(Ordering::Relaxed) >= actor_total.load(Ordering::Relaxed) {
-                    state.trans(Instrument::Finish);
-                }
-                msg.tx.send(Ok(())).unwrap();
+                msg.tx.send(Ok(())).ok();
             }
         });
         Ok(())
@@ -177,6 +306,173 @@ impl Handler<RunTask> for TaskActor {
 }
 //endregion RunTask Message
 
+// region RunPlaylist Message
+
+/// Download a HLS or DASH playlist with ffmpeg, which can't be paused, only cancelled.
+/// Its size is unknown, the progress is the bytes written so far
+#[derive(Message)]
+#[rtype(result = "ActorResult<()>")]
+pub struct RunPlaylist {
+    suffix: String,
+    url: Url,
+    /// Options of ffmpeg for the requests, see [`crate::net::ffmpeg_input`]
+    input: Vec<String>,
+    temp_dir: Arc<TempDirHandler>,
+    tx: oneshot::Sender<ActorResult<()>>,
+}
+
+impl RunPlaylist {
+    pub fn new<S>(
+        suffix: S,
+        url: Url,
+        input: Vec<String>,
+        temp_dir: Arc<TempDirHandler>,
+        tx: oneshot::Sender<ActorResult<()>>,
+    ) -> Self
+    where
+        S: AsRef<str>,
+    {
+        Self {
+            suffix: suffix.as_ref().to_string(),
+            url,
+            input,
+            temp_dir,
+            tx,
+        }
+    }
+}
+
+impl Handler<RunPlaylist> for TaskActor {
+    type Result = ActorResult<()>;
+
+    #[instrument(level=Level::DEBUG, skip(self, msg, _ctx), fields(url=msg.url.as_str()), err)]
+    fn handle(&mut self, msg: RunPlaylist, _ctx: &mut Self::Context) -> Self::Result {
+        let actor_finished = self.finished.clone();
+        let state = self.state.clone();
+        actix_rt::spawn(async move {
+            let cancelled = || state.now() == State::Cancelled;
+            let ret = msg
+                .temp_dir
+                .fetch_playlist(
+                    msg.url.as_str(),
+                    &msg.suffix,
+                    msg.input,
+                    &actor_finished,
+                    cancelled,
+                )
+                .await;
+            let ret = match ret {
+                Ok(()) => Ok(()),
+                Err(TemDirError::MergeCancelled) => actor_error::Cancelled.fail(),
+                Err(e) => {
+                    state.trans(Instrument::Fail);
+                    Err(e.into())
+                }
+            };
+            msg.tx.send(ret).ok();
+        });
+        Ok(())
+    }
+}
+
+// endregion RunPlaylist Message
+
+// region Merge Message
+
+/// Merge the downloaded parts, then run the post processors on the output in order.
+/// The progress switches to the bytes written by ffmpeg, each post processor is a stage.
+#[derive(Message)]
+#[rtype(result = "ActorResult<()>")]
+pub struct Merge {
+    temp_dir: Arc<TempDirHandler>,
+    mode: DownloadMode,
+    audio_format: Option<AudioFormat>,
+    post_process: Vec<Box<dyn PostProcessor>>,
+    tx: oneshot::Sender<ActorResult<()>>,
+}
+
+impl Merge {
+    pub fn new(
+        temp_dir: Arc<TempDirHandler>,
+        mode: DownloadMode,
+        audio_format: Option<AudioFormat>,
+        post_process: Vec<Box<dyn PostProcessor>>,
+        tx: oneshot::Sender<ActorResult<()>>,
+    ) -> Self {
+        Self {
+            temp_dir,
+            mode,
+            audio_format,
+            post_process,
+            tx,
+        }
+    }
+}
+
+impl Handler<Merge> for TaskActor {
+    type Result = ActorResult<()>;
+
+    fn handle(&mut self, msg: Merge, _ctx: &mut Self::Context) -> Self::Result {
+        let actor_finished = self.finished.clone();
+        let state = self.state.clone();
+        if state.now() == State::Cancelled {
+            msg.tx.send(actor_error::Cancelled.fail()).ok();
+            return Ok(());
+        }
+        state.trans(Instrument::Merge);
+        actor_finished.store(0, Ordering::Relaxed);
+        let actor_total = self.total.clone();
+        let stage = self.stage.clone();
+        actix_rt::spawn(async move {
+            let cancelled = || state.now() == State::Cancelled;
+            let ret = msg
+                .temp_dir
+                .save(msg.mode, msg.audio_format, &actor_finished, &cancelled)
+                .await;
+            let ret = match ret {
+                Ok(Some(path)) if !msg.post_process.is_empty() => {
+                    state.trans(Instrument::Process);
+                    let steps = msg.post_process.len();
+                    let on_stage = |i: usize, processor: &dyn PostProcessor, input: &Path| {
+                        *stage.lock().unwrap() =
+                            Some(format!("{} ({}/{})", processor.name(), i + 1, steps));
+                        let size = input.metadata().map_or(0, |m| m.len() as usize);
+                        actor_total.store(size, Ordering::Relaxed);
+                        actor_finished.store(0, Ordering::Relaxed);
+                    };
+                    run_chain(
+                        &msg.post_process,
+                        path,
+                        &actor_finished,
+                        on_stage,
+                        &cancelled,
+                    )
+                    .await
+                    .map(|_| ())
+                }
+                ret => ret.map(|_| ()),
+            };
+            let ret = match ret {
+                Ok(_) => {
+                    actor_finished.store(actor_total.load(Ordering::Relaxed), Ordering::Relaxed);
+                    state.trans(Instrument::Finish);
+                    Ok(())
+                }
+                Err(TemDirError::MergeCancelled) => actor_error::Cancelled.fail(),
+                Err(e) => {
+                    tracing::error!("merging or post processing failed: {}", e);
+                    state.trans(Instrument::Fail);
+                    Err(ActorError::MergeError { source: e })
+                }
+            };
+            msg.tx.send(ret).ok();
+        });
+        Ok(())
+    }
+}
+
+// endregion Merge Message
+
 // region Pause Message
 #[derive(Message)]
 #[rtype(result = "ActorResult<()>")]
@@ -203,6 +499,13 @@ impl Handler<Continue_> for TaskActor {
     type Result = ActorResult<()>;
 
     fn handle(&mut self, _msg: Continue_, _ctx: &mut Self::Context) -> Self::Result {
+        if self.temp_dir.as_ref().is_some_and(|t| t.low_space()) {
+            self.warning = Some(LOW_SPACE.to_string());
+            return Ok(());
+        }
+        if self.warning.as_deref() == Some(LOW_SPACE) {
+            self.warning = None;
+        }
         self.state.trans(Instrument::Continue);
         Ok(())
     }
@@ -260,14 +563,18 @@ impl Handler<Restart> for TaskActor {
 // endregion Restart Message
 
 // region ProgressQuery Message
+
+/// Filename, finished and total bytes, state and warning
+pub type Progress = (String, usize, usize, String, String);
+
 #[derive(Message)]
 #[rtype(result = "ActorResult<()>")]
 pub struct ProgressQuery {
-    tx: oneshot::Sender<ActorResult<(String, usize, usize, String)>>,
+    tx: oneshot::Sender<ActorResult<Progress>>,
 }
 
 impl ProgressQuery {
-    pub fn new(tx: oneshot::Sender<ActorResult<(String, usize, usize, String)>>) -> Self {
+    pub fn new(tx: oneshot::Sender<ActorResult<Progress>>) -> Self {
         Self { tx }
     }
 }
@@ -279,18 +586,25 @@ impl Handler<ProgressQuery> for TaskActor {
         let finished = self.finished.load(Ordering::Relaxed);
         let total = self.total.load(Ordering::Relaxed);
         let state = match self.state.now() {
-            State::Downloading => "downloading",
-            State::Pausing => "pausing",
-            State::Paused => "paused",
-            State::Cancelled => "cancelled",
-            State::Finished => "finished",
+            State::Downloading => "downloading".to_string(),
+            State::Pausing => "pausing".to_string(),
+            State::Paused => "paused".to_string(),
+            State::Cancelled => "cancelled".to_string(),
+            State::Finished => "finished".to_string(),
+            State::Merging => "merging".to_string(),
+            State::Failed => "failed".to_string(),
+            State::Processing => match self.stage.lock().unwrap().as_deref() {
+                Some(stage) => format!("processing: {stage}"),
+                None => "processing".to_string(),
+            },
         };
         msg.tx
             .send(Ok((
                 self.filename.as_deref().unwrap_or("unknown").to_owned(),
                 finished,
                 total,
-                state.to_string(),
+                state,
+                self.warning.clone().unwrap_or_default(),
             )))
             .unwrap();
         Ok(())
@@ -316,10 +630,65 @@ impl Handler<SetFilename> for TaskActor {
 
 // endregion SetFilename Message
 
-async fn get_total(client: Arc<Client>, url: Url, referer: &str) -> Option<usize> {
+// region SetRateLimit Message
+
+#[derive(Message)]
+#[rtype(result = "ActorResult<()>")]
+pub struct SetRateLimit(pub Option<u64>);
+
+impl Handler<SetRateLimit> for TaskActor {
+    type Result = ActorResult<()>;
+
+    fn handle(&mut self, msg: SetRateLimit, _ctx: &mut Self::Context) -> Self::Result {
+        self.limiter = msg.0.map(|limit| Arc::new(RateLimiter::new(limit)));
+        Ok(())
+    }
+}
+
+// endregion SetRateLimit Message
+
+// region SetWarning Message
+
+#[derive(Message)]
+#[rtype(result = "ActorResult<()>")]
+pub struct SetWarning(pub Option<String>);
+
+impl Handler<SetWarning> for TaskActor {
+    type Result = ActorResult<()>;
+
+    fn handle(&mut self, msg: SetWarning, _ctx: &mut Self::Context) -> Self::Result {
+        self.warning = msg.0;
+        Ok(())
+    }
+}
+
+// endregion SetWarning Message
+
+// region SetFailed Message
+
+/// A task which failed before or after its streams, e.g. extracting or preparing them.
+/// The error is shown as the warning, a cancelled task stays cancelled
+#[derive(Message)]
+#[rtype(result = "ActorResult<()>")]
+pub struct SetFailed(pub String);
+
+impl Handler<SetFailed> for TaskActor {
+    type Result = ActorResult<()>;
+
+    fn handle(&mut self, msg: SetFailed, _ctx: &mut Self::Context) -> Self::Result {
+        if self.state.now() != State::Cancelled {
+            self.state.trans(Instrument::Fail);
+            self.warning = Some(msg.0);
+        }
+        Ok(())
+    }
+}
+
+// endregion SetFailed Message
+
+pub(super) async fn get_total(client: Arc<Client>, url: Url) -> Option<usize> {
     client
         .get(url)
-        .header("Referer", referer)
         .header("Range", "bytes=0-0".to_string())
         .send()
         .await
@@ -343,12 +712,22 @@ mod tests {
     async fn run_task_test() {
         crate::config::config_init().unwrap();
         let addr = TaskActor::new().start();
-        let temp_dir = Arc::new(TempDirHandler::new("file").unwrap());
+        let temp_dir = Arc::new(
+            TempDirHandler::new(
+                &uuid::Uuid::new_v4(),
+                "file",
+                Default::default(),
+                None,
+                None,
+            )
+            .unwrap(),
+        );
         let (tx, rx) = tokio::sync::oneshot::channel();
         let run_task = RunTask::new(
             "mp4",
             Url::parse("https://upos-sz-mirror08c.bilivideo.com/upgcxcode/66/77/1049107766/1049107766-1-30112.m4s?e=ig8euxZM2rNcNbdlhoNvNC8BqJIzNbfqXBvEqxTEto8BTrNvN0GvT90W5JZMkX_YN0MvXg8gNEV4NC8xNEV4N03eN0B5tZlqNxTEto8BTrNvNeZVuJ10Kj_g2UB02J0mN0B5tZlqNCNEto8BTrNvNC7MTX502C8f2jmMQJ6mqF2fka1mqx6gqj0eN0B599M=&uipk=5&nbs=1&deadline=1698616254&gen=playurlv2&os=08cbv&oi=3736210139&trid=db65754bb9494698aa13ec17f376d111u&mid=32280488&platform=pc&upsig=a8b17c487797cac95a5fc6e967f81eaf&uparams=e,uipk,nbs,deadline,gen,os,oi,trid,mid,platform&bvc=vod&nettype=0&orderid=0,3&buvid=&build=0&f=u_0_0&agrr=1&bw=669180&logo=80000000").unwrap(),
-            "https://www.bilibili.com/",
+            None,
+            crate::net::client(Some("bili")).unwrap(),
             temp_dir.clone(),tx
         );
         assert!(addr.send(run_task).await.is_ok());
@@ -357,7 +736,8 @@ mod tests {
         let run_task = RunTask::new(
             "aac",
             Url::parse("https://upos-sz-mirrorali.bilivideo.com/upgcxcode/66/77/1049107766/1049107766-1-30280.m4s?e=ig8euxZM2rNcNbdlhoNvNC8BqJIzNbfqXBvEqxTEto8BTrNvN0GvT90W5JZMkX_YN0MvXg8gNEV4NC8xNEV4N03eN0B5tZlqNxTEto8BTrNvNeZVuJ10Kj_g2UB02J0mN0B5tZlqNCNEto8BTrNvNC7MTX502C8f2jmMQJ6mqF2fka1mqx6gqj0eN0B599M=&uipk=5&nbs=1&deadline=1698616254&gen=playurlv2&os=alibv&oi=3736210139&trid=db65754bb9494698aa13ec17f376d111u&mid=32280488&platform=pc&upsig=7a99aaee8fa3f4466c1fe804770f3264&uparams=e,uipk,nbs,deadline,gen,os,oi,trid,mid,platform&bvc=vod&nettype=0&orderid=0,3&buvid=&build=0&f=u_0_0&agrr=1&bw=30625&logo=80000000").unwrap(),
-            "https://www.bilibili.com/",
+            None,
+            crate::net::client(Some("bili")).unwrap(),
             temp_dir.clone(),tx
         );
         assert!(addr.send(run_task).await.is_ok());
@@ -365,6 +745,41 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
     }
 
+    #[actix_rt::test]
+    async fn rate_limit_test() {
+        let limiter = RateLimiter::new(1 << 20);
+        let start = Instant::now();
+        for _ in 0..4 {
+            limiter.consume(1 << 18).await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[actix_rt::test]
+    async fn set_failed_test() {
+        let progress = |addr: Addr<TaskActor>| async move {
+            let (tx, rx) = oneshot::channel();
+            addr.send(ProgressQuery::new(tx)).await.unwrap().unwrap();
+            rx.await.unwrap().unwrap()
+        };
+        let addr = TaskActor::new().start();
+        addr.send(SetFailed("no stream".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        let (_, _, _, state, warning) = progress(addr).await;
+        assert_eq!((state.as_str(), warning.as_str()), ("failed", "no stream"));
+
+        let addr = TaskActor::new().start();
+        addr.send(Cancel).await.unwrap().unwrap();
+        addr.send(SetFailed("cancelled".to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        let (_, _, _, state, warning) = progress(addr).await;
+        assert_eq!((state.as_str(), warning.as_str()), ("cancelled", ""));
+    }
+
     #[test]
     fn state_test() {
         let state = TaskState::new();
@@ -373,6 +788,10 @@ mod tests {
         assert_eq!(state.now(), State::Pausing);
         state.trans(Instrument::Paused);
         assert_eq!(state.now(), State::Paused);
+        state.trans(Instrument::Merge);
+        assert_eq!(state.now(), State::Merging);
+        state.trans(Instrument::Fail);
+        assert_eq!(state.now(), State::Failed);
         state.trans(Instrument::Finish);
         assert_eq!(state.now(), State::Finished);
     }
@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, path::PathBuf};
 
 use snafu::prelude::*;
 
@@ -10,6 +10,29 @@ pub enum TemDirError {
         context(false)
     )]
     IoError { source: io::Error },
+    #[snafu(display(
+        "Not enough disk space in {}: {} bytes required, {} available",
+        dir.display(),
+        required,
+        available
+    ))]
+    NoSpace {
+        dir: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    #[snafu(display("Save dir is not writable: {}", dir.display()))]
+    SaveDirUnwritable { dir: PathBuf, source: io::Error },
+    #[snafu(display("Save dir is not configured"), context(suffix(false)))]
+    SaveDirUnknown,
+    #[snafu(display("Could not run ffmpeg `{}`: {}", program, source))]
+    FfmpegSpawn { program: String, source: io::Error },
+    #[snafu(display("ffmpeg exited with {:?}: {}", code, stderr))]
+    FfmpegFailed { code: Option<i32>, stderr: String },
+    #[snafu(context(suffix(false)))]
+    MergeCancelled,
+    #[snafu(display("Could not remux without ffmpeg: {}", reason))]
+    MuxUnsupported { reason: &'static str },
 }
 
 pub type TempDirResult<T> = Result<T, TemDirError>;
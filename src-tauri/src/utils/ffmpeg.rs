@@ -0,0 +1,330 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use snafu::{ensure, ResultExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::Command,
+};
+
+use crate::config::get_config;
+
+use super::error::{tem_dir_error, TempDirResult};
+
+/// How often cancellation is checked while ffmpeg runs
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Oldest major version known to work, older ones lack AV1 in mp4
+const MIN_MAJOR: u32 = 4;
+/// Where ffmpeg is usually installed when it is not in PATH,
+/// apps started from Finder do not get the PATH of the shell
+const COMMON_DIRS: &[&str] = &[
+    "/opt/homebrew/bin",
+    "/usr/local/bin",
+    "/opt/local/bin",
+    "/usr/bin",
+    "/snap/bin",
+    "C:\\ffmpeg\\bin",
+    "C:\\Program Files\\ffmpeg\\bin",
+];
+
+/// The configured ffmpeg resolved to a full path if possible
+pub fn program() -> String {
+    let configured = get_config("ffmpeg").unwrap_or("ffmpeg".to_string());
+    locate(&configured)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or(configured)
+}
+
+/// Find `configured` as a path, then by name in PATH, then in [`COMMON_DIRS`]
+pub fn locate(configured: &str) -> Option<PathBuf> {
+    let configured = Path::new(configured);
+    if configured.components().count() > 1 {
+        return configured.is_file().then(|| configured.to_path_buf());
+    }
+    let name = format!("{}{}", configured.display(), std::env::consts::EXE_SUFFIX);
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(COMMON_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join(&name))
+        .find(|p| p.is_file())
+}
+
+/// A codec or encoder some feature needs
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Capability {
+    pub name: &'static str,
+    pub needed_for: &'static str,
+    pub available: bool,
+}
+
+/// What was found about ffmpeg, shown in the UI
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Diagnostics {
+    pub path: Option<PathBuf>,
+    pub version: Option<String>,
+    pub capabilities: Vec<Capability>,
+    pub problems: Vec<String>,
+}
+
+/// Locate and probe the configured ffmpeg, never fails, problems are collected
+pub fn diagnose() -> Diagnostics {
+    let configured = get_config("ffmpeg").unwrap_or("ffmpeg".to_string());
+    let mut ret = Diagnostics {
+        path: locate(&configured),
+        ..Default::default()
+    };
+    let Some(path) = ret.path.clone() else {
+        ret.problems.push(format!(
+            "ffmpeg `{configured}` is not found, only bilibili merges work"
+        ));
+        return ret;
+    };
+    match version(&path) {
+        Ok(version) => {
+            if major(&version).is_some_and(|m| m < MIN_MAJOR) {
+                ret.problems.push(format!(
+                    "ffmpeg {version} is too old, {MIN_MAJOR}.0 or newer is required"
+                ));
+            }
+            ret.version = Some(version);
+        }
+        Err(e) => {
+            ret.problems.push(e.to_string());
+            return ret;
+        }
+    }
+    match output(&path, "-codecs") {
+        Ok(codecs) => {
+            ret.capabilities = capabilities(&codecs);
+            ret.problems.extend(
+                ret.capabilities
+                    .iter()
+                    .filter(|c| !c.available)
+                    .map(|c| format!("{} is not supported, needed for {}", c.name, c.needed_for)),
+            );
+        }
+        Err(e) => ret.problems.push(e.to_string()),
+    }
+    ret
+}
+
+/// Locate `configured` and return its version, failing if it does not run
+pub fn check(configured: &str) -> TempDirResult<String> {
+    version(locate(configured).unwrap_or(PathBuf::from(configured)))
+}
+
+/// The version of the ffmpeg at `program`, failing if it does not run
+pub fn version<P: AsRef<Path>>(program: P) -> TempDirResult<String> {
+    let out = output(program.as_ref(), "-version")?;
+    Ok(out
+        .lines()
+        .next()
+        .and_then(|l| l.strip_prefix("ffmpeg version "))
+        .and_then(|l| l.split_whitespace().next())
+        .unwrap_or("unknown")
+        .to_string())
+}
+
+fn output(program: &Path, arg: &str) -> TempDirResult<String> {
+    let out = std::process::Command::new(program)
+        .args(["-hide_banner", arg])
+        .stdin(Stdio::null())
+        .output()
+        .context(tem_dir_error::FfmpegSpawnError {
+            program: program.to_string_lossy(),
+        })?;
+    ensure!(
+        out.status.success(),
+        tem_dir_error::FfmpegFailedError {
+            code: out.status.code(),
+            stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+    );
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+/// `6.1.1`, `n6.0` and `4.4.2-0ubuntu0.22.04.1` give the major,
+/// git builds like `N-112345-gabcdef` give none
+fn major(version: &str) -> Option<u32> {
+    version
+        .trim_start_matches('n')
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Check the output of `ffmpeg -codecs`, a line looks like
+/// ` DEA.L. mp3  MP3 (MPEG audio layer 3) (decoders: mp3float mp3) (encoders: libmp3lame)`
+fn capabilities(codecs: &str) -> Vec<Capability> {
+    let codec = |name: &str| {
+        codecs.lines().find_map(|l| {
+            let mut parts = l.split_whitespace();
+            let flags = parts.next()?;
+            (flags.len() == 6 && parts.next()? == name).then_some((flags, l))
+        })
+    };
+    let has_codec = |name| codec(name).is_some();
+    // the encoders are listed when they are not named after the codec
+    let has_encoder = |name: &str, encoder: &str| {
+        codec(name).is_some_and(|(flags, line)| {
+            flags.as_bytes()[1] == b'E'
+                && match line.split_once("(encoders:") {
+                    Some((_, list)) => list
+                        .split(|c: char| c.is_whitespace() || c == ')')
+                        .any(|e| e == encoder),
+                    None => name == encoder,
+                }
+        })
+    };
+    vec![
+        Capability {
+            name: "av1",
+            needed_for: "AV1 videos",
+            available: has_codec("av1"),
+        },
+        Capability {
+            name: "hevc",
+            needed_for: "HEVC videos",
+            available: has_codec("hevc"),
+        },
+        Capability {
+            name: "libmp3lame",
+            needed_for: "mp3 output",
+            available: has_encoder("mp3", "libmp3lame"),
+        },
+        Capability {
+            name: "libx264",
+            needed_for: "the h264 post processor",
+            available: has_encoder("h264", "libx264"),
+        },
+        Capability {
+            name: "flac",
+            needed_for: "flac output",
+            available: has_encoder("flac", "flac"),
+        },
+    ]
+}
+
+/// Run ffmpeg with `args`, storing the bytes written so far into `progress`.
+/// ffmpeg is killed once `cancelled` returns true.
+pub async fn run<I, S, F>(args: I, progress: &AtomicUsize, cancelled: F) -> TempDirResult<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+    F: Fn() -> bool,
+{
+    let program = program();
+    let mut child = Command::new(&program)
+        .args(["-hide_banner", "-nostdin", "-loglevel", "error"])
+        .args(["-progress", "pipe:1"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context(tem_dir_error::FfmpegSpawnError { program })?;
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut stderr = child.stderr.take().unwrap();
+
+    let watch = async {
+        let mut tick = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => {
+                        if let Some(size) = line
+                            .strip_prefix("total_size=")
+                            .and_then(|s| s.trim().parse::<usize>().ok())
+                        {
+                            progress.store(size, Ordering::Relaxed);
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                _ = tick.tick() => {
+                    if cancelled() {
+                        child.start_kill()?;
+                        return tem_dir_error::MergeCancelled.fail();
+                    }
+                }
+            }
+        }
+    };
+    let read_stderr = async {
+        let mut buf = String::new();
+        stderr.read_to_string(&mut buf).await.ok();
+        buf
+    };
+    let (watched, stderr) = tokio::join!(watch, read_stderr);
+    watched?;
+
+    let status = child.wait().await?;
+    ensure!(
+        status.success(),
+        tem_dir_error::FfmpegFailedError {
+            code: status.code(),
+            stderr: stderr.trim().to_string(),
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::TemDirError;
+
+    const CODECS: &str = "\
+Codecs:
+ D..... = Decoding supported
+ -------
+ DEV.L. av1                  Alliance for Open Media AV1 (decoders: libdav1d av1) (encoders: libaom-av1)
+ DEV.LS h264                 H.264 / AVC / MPEG-4 AVC (encoders: libx264 h264_videotoolbox)
+ DEA.L. mp3                  MP3 (MPEG audio layer 3) (decoders: mp3float mp3)
+ DEA..S flac                 FLAC (Free Lossless Audio Codec)
+";
+
+    #[test]
+    fn capabilities_test() {
+        let caps = capabilities(CODECS)
+            .into_iter()
+            .map(|c| (c.name, c.available))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            caps,
+            [
+                ("av1", true),
+                ("hevc", false),
+                ("libmp3lame", false),
+                ("libx264", true),
+                ("flac", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn version_test() {
+        assert_eq!(major("6.1.1"), Some(6));
+        assert_eq!(major("n4.4"), Some(4));
+        assert_eq!(major("4.4.2-0ubuntu0.22.04.1"), Some(4));
+        assert_eq!(major("N-112345-gabcdef"), None);
+        assert!(locate("/not/exist/ffmpeg").is_none());
+        assert!(version("/not/exist/ffmpeg").is_err());
+    }
+
+    #[tokio::test]
+    async fn ffmpeg_fail_test() {
+        let progress = AtomicUsize::new(0);
+        let ret = run(["-i", "/not/exist.m4s", "out.mp4"], &progress, || false).await;
+        assert!(matches!(
+            ret,
+            Err(TemDirError::FfmpegFailed { .. }) | Err(TemDirError::FfmpegSpawn { .. })
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    fs::File,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use snafu::ensure;
+
+use super::error::{tem_dir_error, TempDirResult};
+
+/// fsync once this many bytes have been written since the last sync
+const SYNC_BYTES: usize = 1 << 24;
+/// or once this much time has passed since the last sync
+const SYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+struct SyncState {
+    unsynced: usize,
+    last: Instant,
+}
+
+/// A file pre-allocated at its final size, written at explicit offsets.
+/// So segments can arrive out of order, and fsync is batched.
+pub struct FileWriter {
+    file: File,
+    sync: Mutex<SyncState>,
+}
+
+impl FileWriter {
+    pub fn create<P: AsRef<Path>>(path: P, size: u64) -> TempDirResult<Self> {
+        let path = path.as_ref();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        let existing = file.metadata()?.len();
+        if existing != size {
+            let dir = path.parent().unwrap_or(Path::new("."));
+            check_space(dir, size.saturating_sub(existing))?;
+            // sparse on most filesystems, blocks are allocated on write
+            file.set_len(size)?;
+        }
+        Ok(Self {
+            file,
+            sync: Mutex::new(SyncState {
+                unsynced: 0,
+                last: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> TempDirResult<()> {
+        write_all_at(&self.file, offset, buf)?;
+        let mut sync = self.sync.lock().unwrap();
+        sync.unsynced += buf.len();
+        if sync.unsynced >= SYNC_BYTES || sync.last.elapsed() >= SYNC_INTERVAL {
+            self.file.sync_data()?;
+            sync.unsynced = 0;
+            sync.last = Instant::now();
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> TempDirResult<()> {
+        let mut sync = self.sync.lock().unwrap();
+        if sync.unsynced > 0 {
+            self.file.sync_data()?;
+            sync.unsynced = 0;
+            sync.last = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
+/// Fail early if `dir` does not have `required` bytes available
+pub fn check_space<P: AsRef<Path>>(dir: P, required: u64) -> TempDirResult<()> {
+    let available = fs2::available_space(dir.as_ref())?;
+    ensure!(
+        available >= required,
+        tem_dir_error::NoSpaceError {
+            dir: dir.as_ref().to_path_buf(),
+            required,
+            available
+        }
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, offset: u64, buf: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut offset: u64, mut buf: &[u8]) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn out_of_order_write_test() {
+        let dir = tempdir::TempDir::new("writer").unwrap();
+        let path = dir.path().join("file.txt");
+        let writer = FileWriter::create(&path, 13).unwrap();
+        assert!(writer.write_at(7, b"world!").is_ok());
+        assert!(writer.write_at(0, b"Hello, ").is_ok());
+        assert!(writer.flush().is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Hello, world!");
+    }
+
+    #[test]
+    fn no_space_test() {
+        let dir = tempdir::TempDir::new("writer").unwrap();
+        let path = dir.path().join("huge");
+        assert!(FileWriter::create(path, u64::MAX).is_err());
+    }
+}
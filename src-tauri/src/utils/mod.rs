@@ -1,10 +1,17 @@
 mod error;
+mod ffmpeg;
+mod file_writer;
+mod mp4;
+mod output;
+mod post_process;
 
 use std::{
-    io::Write,
+    collections::HashMap,
     path::{Path, PathBuf},
+    sync::atomic::AtomicUsize,
 };
 use tempdir::TempDir;
+use uuid::Uuid;
 
 #[cfg(test)]
 use std::io::{self, Read};
@@ -13,15 +20,27 @@ use tracing::{debug, instrument, Level};
 
 use crate::config::get_config;
 
-use error::TempDirResult;
+pub use error::TemDirError;
+use error::{tem_dir_error, TempDirResult};
+pub use ffmpeg::{check as check_ffmpeg, diagnose, Diagnostics};
+use file_writer::check_space;
+pub use file_writer::FileWriter;
+pub use output::DownloadMode;
+use output::{merge_codec, output_path, resolve_conflict, ConflictPolicy};
+pub use post_process::{from_spec, run_chain, AudioFormat, PostProcessor};
+use snafu::{OptionExt, ResultExt};
 
 // region TempDir
 
 #[cfg_attr(test, derive(Debug))]
 pub struct TempDirHandler {
-    temp_dir: TempDir,
+    temp_dir: PathBuf,
     filename: String,
+    fields: HashMap<&'static str, String>,
+    template: Option<String>,
     o_p: PathBuf,
+    /// Bytes kept free on disk, read from config once per task
+    reserve: u64,
 }
 
 #[cfg(test)]
@@ -32,75 +51,168 @@ impl Drop for TempDirHandler {
 }
 
 impl TempDirHandler {
-    pub fn new<S: AsRef<str>>(filename: S) -> TempDirResult<Self> {
+    /// The parts are kept in `{temp_root}/{id}`, so a task with the same id
+    /// finds them again after a restart.
+    /// `fields` fill `template`, or `filename_template` in config, when saving.
+    /// `save_dir` falls back to config too.
+    pub fn new<S: AsRef<str>>(
+        id: &Uuid,
+        filename: S,
+        fields: HashMap<&'static str, String>,
+        save_dir: Option<&Path>,
+        template: Option<&str>,
+    ) -> TempDirResult<Self> {
         let filename = sanitize_filename::sanitize(filename);
-        let temp_dir = TempDir::new("downloader")?;
-        let o_p = if cfg!(test) {
-            temp_dir.path().to_path_buf()
-        } else {
-            Path::new(&get_config("save_dir").unwrap()).to_path_buf()
+        let o_p = match save_dir {
+            Some(dir) if !cfg!(test) => dir.to_path_buf(),
+            _ => self::save_dir()?,
         };
-        std::fs::create_dir_all(&o_p).unwrap();
+        ensure_writable(&o_p)?;
+        let temp_dir = temp_root(&o_p).join(id.to_string());
+        std::fs::create_dir_all(&temp_dir)?;
         Ok(Self {
             temp_dir,
             filename,
+            fields,
+            template: template.map(str::to_string),
             o_p,
+            reserve: disk_reserve(),
         })
     }
 
-    pub fn write<Su: AsRef<str>>(&self, suffix: Su, buf: &[u8]) -> TempDirResult<()> {
-        let path = self
-            .temp_dir
-            .path()
-            .join(format!("{}.{}", self.filename, suffix.as_ref()));
-        let mut f = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)?;
-        f.write_all(buf)?;
-        f.sync_all()?;
+    /// Pre-allocate `{filename}.{suffix}` at `size` bytes and return a positional writer
+    pub fn writer<Su: AsRef<str>>(&self, suffix: Su, size: u64) -> TempDirResult<FileWriter> {
+        FileWriter::create(self.part(suffix), size)
+    }
+
+    fn part<Su: AsRef<str>>(&self, suffix: Su) -> PathBuf {
+        self.temp_dir
+            .join(format!("{}.{}", self.filename, suffix.as_ref()))
+    }
+
+    /// Copy the streams of a HLS or DASH playlist into `{filename}.{suffix}` with ffmpeg,
+    /// `input` are its options for the requests, e.g. the headers.
+    /// The bytes written so far are stored into `progress`, ffmpeg is killed once
+    /// `cancelled` returns true.
+    pub async fn fetch_playlist<Su, F>(
+        &self,
+        url: &str,
+        suffix: Su,
+        input: Vec<String>,
+        progress: &AtomicUsize,
+        cancelled: F,
+    ) -> TempDirResult<()>
+    where
+        Su: AsRef<str>,
+        F: Fn() -> bool,
+    {
+        let part = self.part(suffix);
+        let mut args = input;
+        args.extend(["-i", url, "-c", "copy", "-y"].map(str::to_string));
+        args.push(part.to_string_lossy().to_string());
+        if let Err(e) = ffmpeg::run(args, progress, cancelled).await {
+            std::fs::remove_file(&part).ok();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Check both the temp dir and the save dir can hold `total` bytes twice
+    /// (the downloaded parts and the merged output) plus the configured reserve.
+    pub fn preflight(&self, total: u64) -> TempDirResult<()> {
+        let required = total.saturating_mul(2).saturating_add(self.reserve);
+        check_space(&self.temp_dir, required)?;
+        check_space(&self.o_p, required)?;
         Ok(())
     }
 
-    pub fn save(&self) {
+    /// Whether free space in the temp dir or the save dir dropped under the reserve
+    pub fn low_space(&self) -> bool {
+        check_space(&self.temp_dir, self.reserve).is_err()
+            || check_space(&self.o_p, self.reserve).is_err()
+    }
+
+    /// Merge the parts into the output file with ffmpeg, returning its path,
+    /// or `None` if skipped for the conflict policy.
+    /// In `AudioOnly` mode the audio is saved as m4a, or transcoded to `audio_format`.
+    /// The bytes written so far are stored into `progress`, ffmpeg is killed once
+    /// `cancelled` returns true. A partial output is removed on failure,
+    /// the parts are left for the caller to clean up or retry.
+    pub async fn save<F>(
+        &self,
+        mode: DownloadMode,
+        audio_format: Option<AudioFormat>,
+        progress: &AtomicUsize,
+        cancelled: F,
+    ) -> TempDirResult<Option<PathBuf>>
+    where
+        F: Fn() -> bool,
+    {
         #[cfg(test)]
         debug!("saving");
-        let mut cmd =
-            std::process::Command::new(get_config("ffmpeg").unwrap_or("ffmpeg".to_string()));
-        for path in std::fs::read_dir(self.temp_dir.path()).unwrap() {
-            let path = path.unwrap();
+        let (ext, codec_args) = merge_codec(mode, audio_format);
+        let o_p = if cfg!(test) {
+            self.o_p.join(format!("merge_test.{ext}"))
+        } else {
+            let mut fields = self.fields.clone();
+            fields.insert("ext", ext.to_string());
+            self.o_p
+                .join(output_path(&fields, self.template.as_deref()))
+        };
+        let Some(o_p) = resolve_conflict(&o_p, ConflictPolicy::from_config()) else {
+            tracing::info!("{:?} already exists, skipped", o_p);
+            return Ok(None);
+        };
+        if let Some(parent) = o_p.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut inputs = vec![];
+        for path in std::fs::read_dir(&self.temp_dir)? {
+            let path = path?;
             if let Some(mime) = new_mime_guess::from_path(path.path()).first() {
                 match mime.type_() {
-                    mime::VIDEO | mime::AUDIO => {
-                        cmd.args(["-i", path.path().to_string_lossy().as_ref()]);
-                    }
+                    mime::VIDEO | mime::AUDIO => inputs.push(path.path()),
                     _ => {
                         self.move_(path.file_name()).ok();
                     }
                 }
             }
         }
-        let o_p = if cfg!(test) {
-            self.o_p.join("merge_test.mp4")
-        } else {
-            self.o_p.join(format!("{}.mp4", self.filename))
-        };
-        cmd.args([
-            "-y",
-            "-c:v",
-            "copy",
-            "-c:a",
-            "copy",
-            o_p.to_string_lossy().as_ref(),
-        ]);
-        let _statu = cmd.status();
+        inputs.sort();
+        // stream copy only, transcoding needs ffmpeg
+        if audio_format.is_none() && get_config("native_mux").as_deref() != Some("false") {
+            match mp4::remux(&inputs, &o_p, progress, &cancelled).await {
+                Ok(()) => return Ok(Some(o_p)),
+                Err(TemDirError::MuxUnsupported { reason }) => {
+                    tracing::info!("falling back to ffmpeg: {}", reason);
+                    std::fs::remove_file(&o_p).ok();
+                }
+                Err(e) => {
+                    std::fs::remove_file(&o_p).ok();
+                    return Err(e);
+                }
+            }
+        }
+        let mut args = vec![];
+        for input in inputs {
+            args.push("-i".to_string());
+            args.push(input.to_string_lossy().to_string());
+        }
+        args.push("-y".to_string());
+        args.extend(codec_args);
+        args.push(o_p.to_string_lossy().to_string());
+        if let Err(e) = ffmpeg::run(args, progress, cancelled).await {
+            std::fs::remove_file(&o_p).ok();
+            return Err(e);
+        }
+        Ok(Some(o_p))
     }
 
     pub fn move_<P>(&self, filename: P) -> std::io::Result<()>
     where
         P: AsRef<Path>,
     {
-        let from = self.temp_dir.path().join(filename.as_ref());
+        let from = self.temp_dir.join(filename.as_ref());
         let to = self.o_p.parent().unwrap().join(filename.as_ref());
         #[cfg(test)]
         debug!("move from {:?} to {:?}", from, to);
@@ -108,10 +220,15 @@ impl TempDirHandler {
         Ok(())
     }
 
+    /// Remove the parts, called once they are merged or the task is cancelled
+    pub fn cleanup(&self) {
+        std::fs::remove_dir_all(&self.temp_dir).ok();
+    }
+
     #[cfg(test)]
     #[instrument(level=Level::DEBUG, skip(self), err)]
     pub fn read(&self, filename: &str) -> io::Result<String> {
-        let file_path = self.temp_dir.path().join(filename);
+        let file_path = self.temp_dir.join(filename);
         let mut f = std::fs::OpenOptions::new().read(true).open(file_path)?;
         let mut buf = String::new();
         f.read_to_string(&mut buf)?;
@@ -121,6 +238,75 @@ impl TempDirHandler {
 
 // endregion TempDir
 
+fn save_dir() -> TempDirResult<PathBuf> {
+    if cfg!(test) {
+        return Ok(std::env::temp_dir().join("downloader_test"));
+    }
+    let save_dir = get_config("save_dir").context(tem_dir_error::SaveDirUnknown)?;
+    Ok(Path::new(&save_dir).to_path_buf())
+}
+
+/// `temp_dir` in config, or `.part` inside the save dir
+fn temp_root(save_dir: &Path) -> PathBuf {
+    match get_config("temp_dir").filter(|dir| !dir.is_empty()) {
+        Some(dir) if !cfg!(test) => PathBuf::from(dir),
+        _ => save_dir.join(".part"),
+    }
+}
+
+/// Remove per-task temp folders not belonging to any of `alive`,
+/// in the temp roots of the save dir in config and of `save_dirs`.
+/// Only folders named by a uuid are touched, the temp root may be shared.
+pub fn clean_orphans(alive: &[Uuid], save_dirs: &[PathBuf]) -> TempDirResult<()> {
+    let mut roots = vec![temp_root(&save_dir()?)];
+    for dir in save_dirs {
+        let root = temp_root(dir);
+        if !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    for root in roots {
+        if let Err(e) = clean_orphans_in(&root, alive) {
+            tracing::warn!("could not clean {:?}: {}", root, e);
+        }
+    }
+    Ok(())
+}
+
+fn clean_orphans_in(root: &Path, alive: &[Uuid]) -> TempDirResult<()> {
+    if !root.exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(root)?.filter_map(|e| e.ok()) {
+        let orphan = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Uuid::parse_str(name).ok())
+            .is_some_and(|id| !alive.contains(&id));
+        if orphan && entry.path().is_dir() {
+            tracing::info!("removing orphaned temp dir {:?}", entry.path());
+            std::fs::remove_dir_all(entry.path()).ok();
+        }
+    }
+    Ok(())
+}
+
+/// Bytes to keep free on disk, `disk_reserve_mb` in config
+fn disk_reserve() -> u64 {
+    get_config("disk_reserve_mb")
+        .and_then(|mb| mb.parse::<u64>().ok())
+        .unwrap_or(512)
+        << 20
+}
+
+fn ensure_writable(dir: &Path) -> TempDirResult<()> {
+    std::fs::create_dir_all(dir)
+        .and_then(|_| TempDir::new_in(dir, ".probe").map(drop))
+        .context(tem_dir_error::SaveDirUnwritableError {
+            dir: dir.to_path_buf(),
+        })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -128,9 +314,11 @@ mod test {
     #[test]
     #[ignore = "don't handle txt"]
     fn temp_dir_test() {
-        let temp_file_handler = TempDirHandler::new("test").unwrap();
-        assert!(temp_file_handler.write("txt", b"Hello, ").is_ok());
-        assert!(temp_file_handler.write("txt", b"world!").is_ok());
+        let temp_file_handler =
+            TempDirHandler::new(&Uuid::new_v4(), "test", HashMap::new(), None, None).unwrap();
+        let writer = temp_file_handler.writer("txt", 13).unwrap();
+        assert!(writer.write_at(0, b"Hello, ").is_ok());
+        assert!(writer.write_at(7, b"world!").is_ok());
         assert_eq!(
             temp_file_handler.read("test.txt").ok(),
             Some("Hello, world!".to_string())
@@ -140,9 +328,38 @@ mod test {
     #[test]
     #[ignore = "don't handle txt"]
     fn move_test() {
-        let temp_file_handler = TempDirHandler::new("test").unwrap();
-        assert!(temp_file_handler.write("txt", b"Hello, world").is_ok());
+        let temp_file_handler =
+            TempDirHandler::new(&Uuid::new_v4(), "test", HashMap::new(), None, None).unwrap();
+        let writer = temp_file_handler.writer("txt", 12).unwrap();
+        assert!(writer.write_at(0, b"Hello, world").is_ok());
+        drop(writer);
         assert!(temp_file_handler.move_("txt").is_ok());
         assert!(std::fs::remove_file("/Users/louis/test.txt").is_ok());
     }
+
+    #[test]
+    fn preflight_test() {
+        let temp_file_handler =
+            TempDirHandler::new(&Uuid::new_v4(), "test", HashMap::new(), None, None).unwrap();
+        assert!(temp_file_handler.preflight(0).is_ok());
+        assert!(matches!(
+            temp_file_handler.preflight(u64::MAX),
+            Err(TemDirError::NoSpace { .. })
+        ));
+        temp_file_handler.cleanup();
+    }
+
+    #[test]
+    fn clean_orphans_test() {
+        let root = TempDir::new("orphans").unwrap();
+        let alive = Uuid::new_v4();
+        let orphan = Uuid::new_v4();
+        for dir in [alive.to_string(), orphan.to_string(), "other".to_string()] {
+            std::fs::create_dir(root.path().join(dir)).unwrap();
+        }
+        assert!(clean_orphans_in(root.path(), &[alive]).is_ok());
+        assert!(root.path().join(alive.to_string()).exists());
+        assert!(!root.path().join(orphan.to_string()).exists());
+        assert!(root.path().join("other").exists());
+    }
 }
@@ -0,0 +1,594 @@
+//! Remux DASH fragmented MP4, as served by bilibili, without ffmpeg.
+//!
+//! Every input is an `ftyp`, a `moov` holding one track and a list of `moof` + `mdat`
+//! fragments. The output is a fragmented MP4 holding all the tracks, with the fragments
+//! interleaved by decode time. Samples are copied as they are, only ids are rewritten.
+//! Anything else is [`MuxUnsupported`](super::TemDirError::MuxUnsupported) so the caller can fall back to ffmpeg.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use snafu::{ensure, OptionExt};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom},
+};
+
+use super::error::{tem_dir_error, TempDirResult};
+
+/// Bytes copied from an `mdat` at a time
+const CHUNK: usize = 1 << 20;
+/// `tfhd` flag for an absolute base offset, which would need rewriting
+const BASE_DATA_OFFSET_PRESENT: u32 = 0x1;
+
+type FourCC = [u8; 4];
+
+/// A box found in a buffer, as offsets into it
+#[derive(Debug, Clone, Copy)]
+struct BoxRef {
+    fourcc: FourCC,
+    start: usize,
+    payload: usize,
+    end: usize,
+}
+
+/// The boxes directly inside `buf[from..to]`
+fn children(buf: &[u8], from: usize, to: usize) -> Vec<BoxRef> {
+    let mut ret = vec![];
+    let mut pos = from;
+    while pos + 8 <= to {
+        let size = read_u32(buf, pos) as usize;
+        let fourcc = buf[pos + 4..pos + 8].try_into().unwrap();
+        let (size, header) = match size {
+            0 => (to - pos, 8),
+            1 if pos + 16 <= to => (read_u64(buf, pos + 8) as usize, 16),
+            _ => (size, 8),
+        };
+        if size < header || size > to - pos {
+            break;
+        }
+        ret.push(BoxRef {
+            fourcc,
+            start: pos,
+            payload: pos + header,
+            end: pos + size,
+        });
+        pos += size;
+    }
+    ret
+}
+
+fn find(buf: &[u8], parent: BoxRef, fourcc: &FourCC) -> Option<BoxRef> {
+    children(buf, parent.payload, parent.end)
+        .into_iter()
+        .find(|b| &b.fourcc == fourcc)
+}
+
+/// `parent/path[0]/path[1]/...`
+fn find_path(buf: &[u8], parent: BoxRef, path: &[&FourCC]) -> Option<BoxRef> {
+    path.iter()
+        .try_fold(parent, |b, fourcc| find(buf, b, fourcc))
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+fn write_u32(buf: &mut [u8], at: usize, value: u32) {
+    buf[at..at + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// The box spanning the whole of `buf`, as read by [`read_box`]
+fn outer(buf: &[u8]) -> TempDirResult<BoxRef> {
+    children(buf, 0, buf.len())
+        .first()
+        .copied()
+        .context(tem_dir_error::MuxUnsupportedError {
+            reason: "truncated box",
+        })
+}
+
+/// Offset of the 32 bit field after the creation and modification times of a full box,
+/// which are 64 bits in version 1. `None` if the box is too short to hold it
+fn after_times(buf: &[u8], b: BoxRef) -> Option<usize> {
+    let at = b.payload + 4 + if *buf.get(b.payload)? == 1 { 16 } else { 8 };
+    (at + 4 <= b.end).then_some(at)
+}
+
+fn wrap(fourcc: &FourCC, payload: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(payload.len() + 8);
+    ret.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    ret.extend_from_slice(fourcc);
+    ret.extend_from_slice(payload);
+    ret
+}
+
+fn unsupported<T>(reason: &'static str) -> TempDirResult<T> {
+    tem_dir_error::MuxUnsupportedError { reason }.fail()
+}
+
+struct Fragment {
+    moof: Vec<u8>,
+    mdat_pos: u64,
+    mdat_size: u64,
+    decode_time: u64,
+}
+
+/// One input file, holding one track
+struct Track {
+    file: File,
+    ftyp: Vec<u8>,
+    moov: Vec<u8>,
+    timescale: u32,
+    fragments: Vec<Fragment>,
+}
+
+impl Track {
+    async fn open(path: &Path) -> TempDirResult<Self> {
+        let mut file = File::open(path).await?;
+        let len = file.metadata().await?.len();
+        let (mut ftyp, mut moov) = (None, None);
+        let mut fragments = vec![];
+        let mut moof = None;
+        let mut pos = 0;
+        while pos + 8 <= len {
+            let (size, fourcc) = read_header(&mut file, pos, len).await?;
+            match &fourcc {
+                b"ftyp" => ftyp = Some(read_box(&mut file, pos, size).await?),
+                b"moov" => moov = Some(read_box(&mut file, pos, size).await?),
+                b"moof" => moof = Some(read_box(&mut file, pos, size).await?),
+                b"mdat" => {
+                    let Some(moof) = moof.take() else {
+                        return unsupported("mdat without moof, not fragmented");
+                    };
+                    fragments.push(Fragment {
+                        decode_time: decode_time(&moof)?,
+                        moof,
+                        mdat_pos: pos,
+                        mdat_size: size,
+                    });
+                }
+                // sidx points into this file, it is dropped
+                _ => {}
+            }
+            pos += size;
+        }
+        let ftyp = ftyp.context(tem_dir_error::MuxUnsupportedError { reason: "no ftyp" })?;
+        let moov = moov.context(tem_dir_error::MuxUnsupportedError { reason: "no moov" })?;
+        let moov_ref = outer(&moov)?;
+        let traks = children(&moov, moov_ref.payload, moov_ref.end)
+            .into_iter()
+            .filter(|b| &b.fourcc == b"trak")
+            .count();
+        ensure!(
+            traks == 1,
+            tem_dir_error::MuxUnsupportedError {
+                reason: "not one track"
+            }
+        );
+        ensure!(
+            find_path(&moov, moov_ref, &[b"mvex", b"trex"]).is_some(),
+            tem_dir_error::MuxUnsupportedError { reason: "no trex" }
+        );
+        let mdhd = find_path(&moov, moov_ref, &[b"trak", b"mdia", b"mdhd"])
+            .and_then(|b| after_times(&moov, b))
+            .context(tem_dir_error::MuxUnsupportedError { reason: "no mdhd" })?;
+        let timescale = read_u32(&moov, mdhd);
+        ensure!(
+            !fragments.is_empty() && timescale != 0,
+            tem_dir_error::MuxUnsupportedError {
+                reason: "no fragments"
+            }
+        );
+        Ok(Self {
+            file,
+            ftyp,
+            moov,
+            timescale,
+            fragments,
+        })
+    }
+
+    fn child(&self, path: &[&FourCC]) -> TempDirResult<Option<Vec<u8>>> {
+        let moov = outer(&self.moov)?;
+        Ok(find_path(&self.moov, moov, path).map(|b| self.moov[b.start..b.end].to_vec()))
+    }
+
+    /// The `trak` with its id set to `id`
+    fn trak(&self, id: u32) -> TempDirResult<Vec<u8>> {
+        let mut trak = self
+            .child(&[b"trak"])?
+            .context(tem_dir_error::MuxUnsupportedError { reason: "no trak" })?;
+        let at = find(&trak, outer(&trak)?, b"tkhd")
+            .and_then(|tkhd| after_times(&trak, tkhd))
+            .context(tem_dir_error::MuxUnsupportedError { reason: "no tkhd" })?;
+        write_u32(&mut trak, at, id);
+        Ok(trak)
+    }
+
+    /// The `trex` with its id set to `id`
+    fn trex(&self, id: u32) -> TempDirResult<Vec<u8>> {
+        let mut trex = self
+            .child(&[b"mvex", b"trex"])?
+            .context(tem_dir_error::MuxUnsupportedError { reason: "no trex" })?;
+        // after the version and flags
+        let at = outer(&trex)?.payload + 4;
+        ensure!(
+            at + 4 <= trex.len(),
+            tem_dir_error::MuxUnsupportedError {
+                reason: "truncated trex"
+            }
+        );
+        write_u32(&mut trex, at, id);
+        Ok(trex)
+    }
+
+    /// Decode time of fragment `i` in seconds is `time / timescale`
+    fn time(&self, i: usize) -> (u64, u32) {
+        (self.fragments[i].decode_time, self.timescale)
+    }
+}
+
+async fn read_header(file: &mut File, pos: u64, len: u64) -> TempDirResult<(u64, FourCC)> {
+    let mut head = [0; 8];
+    file.seek(SeekFrom::Start(pos)).await?;
+    file.read_exact(&mut head).await?;
+    let fourcc = head[4..].try_into().unwrap();
+    let (size, header) = match read_u32(&head, 0) {
+        0 => (len - pos, 8),
+        1 => {
+            let mut large = [0; 8];
+            file.read_exact(&mut large).await?;
+            (u64::from_be_bytes(large), 16)
+        }
+        size => (size as u64, 8),
+    };
+    ensure!(
+        size >= header && pos + size <= len,
+        tem_dir_error::MuxUnsupportedError {
+            reason: "truncated box"
+        }
+    );
+    Ok((size, fourcc))
+}
+
+async fn read_box(file: &mut File, pos: u64, size: u64) -> TempDirResult<Vec<u8>> {
+    let mut buf = vec![0; size as usize];
+    file.seek(SeekFrom::Start(pos)).await?;
+    file.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// The one `traf` of a `moof`, checking the data offsets are relative to the `moof`
+fn traf(moof: &[u8]) -> TempDirResult<BoxRef> {
+    let moof_ref = outer(moof)?;
+    let trafs = children(moof, moof_ref.payload, moof_ref.end)
+        .into_iter()
+        .filter(|b| &b.fourcc == b"traf")
+        .collect::<Vec<_>>();
+    let [traf] = trafs[..] else {
+        return unsupported("not one traf in moof");
+    };
+    let tfhd = find(moof, traf, b"tfhd")
+        .filter(|b| b.payload + 8 <= b.end)
+        .context(tem_dir_error::MuxUnsupportedError { reason: "no tfhd" })?;
+    ensure!(
+        read_u32(moof, tfhd.payload) & BASE_DATA_OFFSET_PRESENT == 0,
+        tem_dir_error::MuxUnsupportedError {
+            reason: "absolute data offset"
+        }
+    );
+    Ok(traf)
+}
+
+fn decode_time(moof: &[u8]) -> TempDirResult<u64> {
+    let tfdt = find(moof, traf(moof)?, b"tfdt")
+        .filter(|b| b.payload + 8 <= b.end)
+        .context(tem_dir_error::MuxUnsupportedError { reason: "no tfdt" })?;
+    match moof[tfdt.payload] {
+        1 if tfdt.payload + 12 <= tfdt.end => Ok(read_u64(moof, tfdt.payload + 4)),
+        1 => unsupported("truncated tfdt"),
+        _ => Ok(read_u32(moof, tfdt.payload + 4) as u64),
+    }
+}
+
+/// The `moof` with its sequence number and track id rewritten.
+/// Its size does not change, so the data offsets stay valid.
+fn rewrite_moof(moof: &[u8], sequence: u32, id: u32) -> TempDirResult<Vec<u8>> {
+    let mut moof = moof.to_vec();
+    let moof_ref = outer(&moof)?;
+    if let Some(mfhd) = find(&moof, moof_ref, b"mfhd").filter(|b| b.payload + 8 <= b.end) {
+        write_u32(&mut moof, mfhd.payload + 4, sequence);
+    }
+    // checked by traf
+    let tfhd = find(&moof, traf(&moof)?, b"tfhd")
+        .context(tem_dir_error::MuxUnsupportedError { reason: "no tfhd" })?;
+    write_u32(&mut moof, tfhd.payload + 4, id);
+    Ok(moof)
+}
+
+/// `moov` of the output, the header of the first track followed by all the tracks
+fn build_moov(tracks: &[Track]) -> TempDirResult<Vec<u8>> {
+    let first = tracks
+        .first()
+        .context(tem_dir_error::MuxUnsupportedError { reason: "no input" })?;
+    let mut mvhd = first.child(&[b"mvhd"])?.unwrap_or_default();
+    let header = children(&mvhd, 0, mvhd.len())
+        .first()
+        .and_then(|b| Some((b.payload, *mvhd.get(b.payload)?)));
+    if let Some((payload, version)) = header {
+        // times, timescale and duration, rate, volume, reserved, matrix and pre_defined
+        let next_track_id = payload + 4 + if version == 1 { 28 } else { 16 } + 76;
+        if next_track_id + 4 <= mvhd.len() {
+            write_u32(&mut mvhd, next_track_id, tracks.len() as u32 + 1);
+        }
+    }
+    let mut mvex = first.child(&[b"mvex", b"mehd"])?.unwrap_or_default();
+    let mut moov = mvhd;
+    for (i, track) in tracks.iter().enumerate() {
+        moov.extend(track.trak(i as u32 + 1)?);
+        mvex.extend(track.trex(i as u32 + 1)?);
+    }
+    moov.extend(wrap(b"mvex", &mvex));
+    Ok(wrap(b"moov", &moov))
+}
+
+/// Remux `inputs` into `output`, storing the bytes written so far into `progress`.
+/// Stops with [`MergeCancelled`](super::TemDirError::MergeCancelled) once `cancelled` returns true.
+pub async fn remux<F>(
+    inputs: &[PathBuf],
+    output: &Path,
+    progress: &AtomicUsize,
+    cancelled: F,
+) -> TempDirResult<()>
+where
+    F: Fn() -> bool,
+{
+    ensure!(
+        !inputs.is_empty(),
+        tem_dir_error::MuxUnsupportedError { reason: "no input" }
+    );
+    let mut tracks = vec![];
+    for input in inputs {
+        tracks.push(Track::open(input).await?);
+    }
+    // moov and moofs are checked here, before the output is created
+    let moov = build_moov(&tracks)?;
+    for (i, track) in tracks.iter().enumerate() {
+        for fragment in &track.fragments {
+            rewrite_moof(&fragment.moof, 0, i as u32 + 1)?;
+        }
+    }
+
+    let mut out = File::create(output).await?;
+    let mut written = 0;
+    for header in [tracks[0].ftyp.clone(), moov] {
+        out.write_all(&header).await?;
+        written += header.len();
+    }
+    let mut next = vec![0; tracks.len()];
+    let mut buf = vec![0; CHUNK];
+    for sequence in 1.. {
+        // the track whose next fragment starts first
+        let Some(i) = (0..tracks.len())
+            .filter(|&i| next[i] < tracks[i].fragments.len())
+            .min_by(|&a, &b| {
+                // time_a / timescale_a against time_b / timescale_b, without floats
+                let (time_a, timescale_a) = tracks[a].time(next[a]);
+                let (time_b, timescale_b) = tracks[b].time(next[b]);
+                (time_a as u128 * timescale_b as u128).cmp(&(time_b as u128 * timescale_a as u128))
+            })
+        else {
+            break;
+        };
+        ensure!(!cancelled(), tem_dir_error::MergeCancelled);
+        let track = &mut tracks[i];
+        let fragment = &track.fragments[next[i]];
+        let moof = rewrite_moof(&fragment.moof, sequence, i as u32 + 1)?;
+        out.write_all(&moof).await?;
+        written += moof.len();
+
+        let (pos, mut remaining) = (fragment.mdat_pos, fragment.mdat_size);
+        track.file.seek(SeekFrom::Start(pos)).await?;
+        while remaining > 0 {
+            let n = remaining.min(CHUNK as u64) as usize;
+            track.file.read_exact(&mut buf[..n]).await?;
+            out.write_all(&buf[..n]).await?;
+            remaining -= n as u64;
+            written += n;
+            progress.store(written, Ordering::Relaxed);
+        }
+        next[i] += 1;
+    }
+    out.flush().await?;
+    out.sync_data().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::TemDirError;
+
+    fn full(fourcc: &FourCC, version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+        let mut body = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+        body.extend_from_slice(payload);
+        wrap(fourcc, &body)
+    }
+
+    fn concat(parts: &[Vec<u8>]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    /// A minimal single track fMP4 with one fragment per `(decode_time, data)`
+    fn fmp4(timescale: u32, fragments: &[(u32, &[u8])]) -> Vec<u8> {
+        truncated(timescale, fragments, 80, 20)
+    }
+
+    /// [`fmp4`] with the payloads of `tkhd` and `trex` cut to `tkhd_len` and `trex_len`
+    fn truncated(
+        timescale: u32,
+        fragments: &[(u32, &[u8])],
+        tkhd_len: usize,
+        trex_len: usize,
+    ) -> Vec<u8> {
+        let mut mvhd = vec![0; 96];
+        mvhd[8..12].copy_from_slice(&timescale.to_be_bytes());
+        mvhd[92..96].copy_from_slice(&2u32.to_be_bytes());
+        let mut tkhd = vec![0; 80];
+        tkhd[8..12].copy_from_slice(&1u32.to_be_bytes());
+        tkhd.truncate(tkhd_len);
+        let mut mdhd = vec![0; 20];
+        mdhd[8..12].copy_from_slice(&timescale.to_be_bytes());
+        let mut trex = [1u32, 1, 0, 0, 0]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect::<Vec<_>>();
+        trex.truncate(trex_len);
+        let moov = wrap(
+            b"moov",
+            &concat(&[
+                full(b"mvhd", 0, 0, &mvhd),
+                wrap(
+                    b"trak",
+                    &concat(&[
+                        full(b"tkhd", 0, 3, &tkhd),
+                        wrap(b"mdia", &full(b"mdhd", 0, 0, &mdhd)),
+                    ]),
+                ),
+                wrap(b"mvex", &full(b"trex", 0, 0, &trex)),
+            ]),
+        );
+        let mut file = concat(&[wrap(b"ftyp", b"iso5\0\0\0\x01iso6mp41"), moov]);
+        file.extend(wrap(b"sidx", &[0; 24]));
+        for (i, (time, data)) in fragments.iter().enumerate() {
+            file.extend(wrap(
+                b"moof",
+                &concat(&[
+                    full(b"mfhd", 0, 0, &(i as u32 + 1).to_be_bytes()),
+                    wrap(
+                        b"traf",
+                        &concat(&[
+                            full(b"tfhd", 0, 0x020000, &1u32.to_be_bytes()),
+                            full(b"tfdt", 0, 0, &time.to_be_bytes()),
+                        ]),
+                    ),
+                ]),
+            ));
+            file.extend(wrap(b"mdat", data));
+        }
+        file
+    }
+
+    /// (fourcc, track id, sequence, mdat) of every fragment in `buf`
+    fn fragments(buf: &[u8]) -> Vec<(u32, u32, Vec<u8>)> {
+        let boxes = children(buf, 0, buf.len());
+        boxes
+            .windows(2)
+            .filter(|w| &w[0].fourcc == b"moof")
+            .map(|w| {
+                let moof = &buf[w[0].start..w[0].end];
+                let moof_ref = children(moof, 0, moof.len())[0];
+                let mfhd = find(moof, moof_ref, b"mfhd").unwrap();
+                let tfhd = find_path(moof, moof_ref, &[b"traf", b"tfhd"]).unwrap();
+                (
+                    read_u32(moof, tfhd.payload + 4),
+                    read_u32(moof, mfhd.payload + 4),
+                    buf[w[1].payload..w[1].end].to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn remux_test() {
+        let dir = tempdir::TempDir::new("mp4").unwrap();
+        let video = dir.path().join("video.mp4");
+        let audio = dir.path().join("audio.m4a");
+        let output = dir.path().join("out.mp4");
+        // video at 1000 per second, audio at 48000 per second
+        std::fs::write(&video, fmp4(1000, &[(0, b"v0"), (2000, b"v1")])).unwrap();
+        std::fs::write(&audio, fmp4(48000, &[(0, b"a0"), (48000, b"a1")])).unwrap();
+
+        let progress = AtomicUsize::new(0);
+        remux(&[video, audio], &output, &progress, || false)
+            .await
+            .unwrap();
+        let buf = std::fs::read(&output).unwrap();
+        assert_eq!(progress.load(Ordering::Relaxed), buf.len());
+
+        let boxes = children(&buf, 0, buf.len());
+        assert_eq!(&boxes[0].fourcc, b"ftyp");
+        let moov = boxes[1];
+        let ids = children(&buf, moov.payload, moov.end)
+            .into_iter()
+            .filter(|b| &b.fourcc == b"trak")
+            .map(|trak| {
+                let tkhd = find(&buf, trak, b"tkhd").unwrap();
+                read_u32(&buf, after_times(&buf, tkhd).unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2]);
+        let mvex = find(&buf, moov, b"mvex").unwrap();
+        assert_eq!(children(&buf, mvex.payload, mvex.end).len(), 2);
+        assert!(boxes.iter().all(|b| &b.fourcc != b"sidx"));
+
+        assert_eq!(
+            fragments(&buf),
+            [
+                (1, 1, b"v0".to_vec()),
+                (2, 2, b"a0".to_vec()),
+                (2, 3, b"a1".to_vec()),
+                (1, 4, b"v1".to_vec()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unsupported_test() {
+        let dir = tempdir::TempDir::new("mp4").unwrap();
+        let input = dir.path().join("plain.mp4");
+        let output = dir.path().join("out.mp4");
+        let plain = concat(&[wrap(b"ftyp", b"isom\0\0\0\0"), wrap(b"mdat", b"data")]);
+        std::fs::write(&input, plain).unwrap();
+        let progress = AtomicUsize::new(0);
+        let ret = remux(&[input], &output, &progress, || false).await;
+        assert!(matches!(ret, Err(TemDirError::MuxUnsupported { .. })));
+        assert!(!output.exists());
+
+        let input = dir.path().join("video.mp4");
+        std::fs::write(&input, fmp4(1000, &[(0, b"v0")])).unwrap();
+        let ret = remux(&[input], &output, &progress, || true).await;
+        assert!(matches!(ret, Err(TemDirError::MergeCancelled)));
+    }
+
+    #[tokio::test]
+    async fn malformed_test() {
+        let dir = tempdir::TempDir::new("mp4").unwrap();
+        let input = dir.path().join("video.mp4");
+        let output = dir.path().join("out.mp4");
+        let progress = AtomicUsize::new(0);
+        let mut cut = fmp4(1000, &[(0, b"v0")]);
+        // ends inside the tfhd of the last moof, before its mdat
+        cut.truncate(cut.len() - 30);
+        for buf in [
+            truncated(1000, &[(0, b"v0")], 0, 20),
+            truncated(1000, &[(0, b"v0")], 8, 20),
+            truncated(1000, &[(0, b"v0")], 80, 0),
+            truncated(1000, &[(0, b"v0")], 80, 2),
+            cut,
+            wrap(b"moov", &wrap(b"trak", &[0; 3])),
+        ] {
+            std::fs::write(&input, buf).unwrap();
+            let ret = remux(std::slice::from_ref(&input), &output, &progress, || false).await;
+            assert!(matches!(ret, Err(TemDirError::MuxUnsupported { .. })));
+            assert!(!output.exists());
+        }
+    }
+}
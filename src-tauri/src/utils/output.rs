@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::AudioFormat;
+use crate::config::get_config;
+
+const DEFAULT_TEMPLATE: &str = "{title}.{ext}";
+const DEFAULT_MAX_LEN: usize = 200;
+
+/// Which streams of the media are downloaded and saved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadMode {
+    #[default]
+    Both,
+    AudioOnly,
+    VideoOnly,
+}
+
+impl std::str::FromStr for DownloadMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(Self::Both),
+            "audio_only" => Ok(Self::AudioOnly),
+            "video_only" => Ok(Self::VideoOnly),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The extension and ffmpeg codec args of the merged output
+pub fn merge_codec(
+    mode: DownloadMode,
+    audio_format: Option<AudioFormat>,
+) -> (&'static str, Vec<String>) {
+    let (ext, args): (_, &[&str]) = match (mode, audio_format) {
+        (DownloadMode::Both, _) => ("mp4", &["-c:v", "copy", "-c:a", "copy"]),
+        (DownloadMode::VideoOnly, _) => ("mp4", &["-an", "-c:v", "copy"]),
+        (DownloadMode::AudioOnly, None) => ("m4a", &["-vn", "-c:a", "copy"]),
+        (DownloadMode::AudioOnly, Some(format)) => {
+            let mut args = vec!["-vn".to_string()];
+            args.extend(format.codec_args());
+            return (format.ext(), args);
+        }
+    };
+    (ext, args.iter().map(|a| a.to_string()).collect())
+}
+
+/// What to do when the output file already exists, `on_conflict` in config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Overwrite,
+    Skip,
+    AutoNumber,
+}
+
+impl ConflictPolicy {
+    pub fn from_config() -> Self {
+        match get_config("on_conflict").as_deref() {
+            Some("overwrite") => Self::Overwrite,
+            Some("skip") => Self::Skip,
+            _ => Self::AutoNumber,
+        }
+    }
+}
+
+/// Render the output path relative to the save dir from `template`,
+/// or `filename_template` in config
+pub fn output_path(fields: &HashMap<&str, String>, template: Option<&str>) -> PathBuf {
+    let template = template
+        .map(str::to_string)
+        .or_else(|| get_config("filename_template"))
+        .filter(|t| !t.is_empty())
+        .unwrap_or(DEFAULT_TEMPLATE.to_string());
+    let max_len = get_config("filename_max_len")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(DEFAULT_MAX_LEN);
+    render(&template, fields, max_len)
+}
+
+/// Replace `{field}` in every `/` separated component, sanitize and truncate each of them.
+/// Unknown fields render empty; empty components are dropped.
+pub fn render(template: &str, fields: &HashMap<&str, String>, max_len: usize) -> PathBuf {
+    let ext = fields
+        .get("ext")
+        .map(|e| format!(".{e}"))
+        .unwrap_or_default();
+    let mut components = template
+        .split('/')
+        .map(|c| sanitize_filename::sanitize(fill(c, fields)))
+        .filter(|c| !c.trim().is_empty())
+        .collect::<Vec<_>>();
+    let last = components.pop().unwrap_or_default();
+    let mut path = components
+        .iter()
+        .map(|c| truncate(c, max_len, ""))
+        .collect::<PathBuf>();
+    if last.trim_end_matches(&ext).trim().is_empty() {
+        let title = sanitize_filename::sanitize(fields.get("title").map_or("unknown", |t| t));
+        path.push(truncate(&format!("{title}{ext}"), max_len, &ext));
+    } else {
+        path.push(truncate(&last, max_len, &ext));
+    }
+    path
+}
+
+fn fill(component: &str, fields: &HashMap<&str, String>) -> String {
+    let mut ret = String::new();
+    let mut rest = component;
+    while let Some(start) = rest.find('{') {
+        ret.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let key = &rest[start + 1..start + end];
+                ret.push_str(fields.get(key).map_or("", |v| v));
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                ret.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    ret.push_str(rest);
+    ret
+}
+
+/// Cut `name` to at most `max_len` bytes on a char boundary, keeping `suffix` intact
+fn truncate(name: &str, max_len: usize, suffix: &str) -> String {
+    if name.len() <= max_len {
+        return name.to_string();
+    }
+    let (stem, suffix) = match name.strip_suffix(suffix) {
+        Some(stem) if !suffix.is_empty() => (stem, suffix),
+        _ => (name, ""),
+    };
+    let mut end = max_len.saturating_sub(suffix.len()).min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end(), suffix)
+}
+
+/// Apply the conflict policy to `path`, `None` means skip
+pub fn resolve_conflict(path: &Path, policy: ConflictPolicy) -> Option<PathBuf> {
+    if !path.exists() {
+        return Some(path.to_path_buf());
+    }
+    match policy {
+        ConflictPolicy::Overwrite => Some(path.to_path_buf()),
+        ConflictPolicy::Skip => None,
+        ConflictPolicy::AutoNumber => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = path
+                .extension()
+                .map(|e| format!(".{}", e.to_string_lossy()))
+                .unwrap_or_default();
+            (1..)
+                .map(|n| path.with_file_name(format!("{stem} ({n}){ext}")))
+                .find(|p| !p.exists())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields() -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("title", "a/b: title".to_string()),
+            ("uploader", "up".to_string()),
+            ("date", "2023-11-01".to_string()),
+            ("bvid", "BV1EC4y1V7ho".to_string()),
+            ("ext", "mp4".to_string()),
+        ])
+    }
+
+    #[test]
+    fn render_test() {
+        assert_eq!(
+            render("{title}.{ext}", &fields(), 200),
+            PathBuf::from("ab title.mp4")
+        );
+        assert_eq!(
+            render("{uploader}/{date} - {title} [{bvid}].{ext}", &fields(), 200),
+            PathBuf::from("up").join("2023-11-01 - ab title [BV1EC4y1V7ho].mp4")
+        );
+        assert_eq!(
+            render("{missing}/{missing}.{ext}", &fields(), 200),
+            PathBuf::from("ab title.mp4")
+        );
+    }
+
+    #[test]
+    fn merge_codec_test() {
+        assert_eq!(merge_codec(DownloadMode::Both, None).0, "mp4");
+        assert!(merge_codec(DownloadMode::VideoOnly, None)
+            .1
+            .contains(&"-an".to_string()));
+        assert_eq!(merge_codec(DownloadMode::AudioOnly, None).0, "m4a");
+        let (ext, args) = merge_codec(DownloadMode::AudioOnly, Some(AudioFormat::Mp3));
+        assert_eq!(ext, "mp3");
+        assert!(args.contains(&"libmp3lame".to_string()));
+        assert_eq!("audio_only".parse(), Ok(DownloadMode::AudioOnly));
+    }
+
+    #[test]
+    fn truncate_test() {
+        assert_eq!(truncate("abcdef.mp4", 8, ".mp4"), "abcd.mp4");
+        assert_eq!(truncate("你好世界.mp4", 11, ".mp4"), "你好.mp4");
+        assert_eq!(truncate("short.mp4", 200, ".mp4"), "short.mp4");
+    }
+
+    #[test]
+    fn conflict_test() {
+        let dir = tempdir::TempDir::new("conflict").unwrap();
+        let path = dir.path().join("a.mp4");
+        assert_eq!(
+            resolve_conflict(&path, ConflictPolicy::Skip),
+            Some(path.clone())
+        );
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(resolve_conflict(&path, ConflictPolicy::Skip), None);
+        assert_eq!(
+            resolve_conflict(&path, ConflictPolicy::Overwrite),
+            Some(path.clone())
+        );
+        std::fs::write(dir.path().join("a (1).mp4"), b"").unwrap();
+        assert_eq!(
+            resolve_conflict(&path, ConflictPolicy::AutoNumber),
+            Some(dir.path().join("a (2).mp4"))
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::AtomicUsize,
+};
+
+use super::{
+    error::TempDirResult,
+    ffmpeg,
+    output::{resolve_conflict, ConflictPolicy},
+};
+
+/// A step run on the saved file after merging.
+/// Built from the names in `post_process` of the task options or config, see [`from_spec`].
+pub trait PostProcessor: std::fmt::Debug + Send + Sync {
+    /// Shown as the stage in task progress
+    fn name(&self) -> String;
+    /// The extension of the file produced
+    fn ext(&self, input: &Path) -> String;
+    /// ffmpeg args reading `input` and writing `output`
+    fn args(&self, input: &Path, output: &Path) -> Vec<String>;
+    /// Whether `input` is kept next to the output, otherwise it is replaced
+    fn keep_input(&self) -> bool {
+        false
+    }
+}
+
+/// Parse one step, e.g. `mkv`, `mp3`, `m4a`, `flac`, `h264`, `loudnorm`,
+/// `trim:00:00:10-00:01:00`
+pub fn from_spec(spec: &str) -> Option<Box<dyn PostProcessor>> {
+    let (name, arg) = match spec.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (spec, None),
+    };
+    let processor: Box<dyn PostProcessor> = match (name.trim(), arg) {
+        ("mkv", None) => Box::new(Remux("mkv")),
+        ("mp3", None) => Box::new(ExtractAudio(AudioFormat::Mp3)),
+        ("m4a", None) => Box::new(ExtractAudio(AudioFormat::M4a)),
+        ("flac", None) => Box::new(ExtractAudio(AudioFormat::Flac)),
+        ("h264", None) => Box::new(ReencodeH264),
+        ("loudnorm", None) => Box::new(Loudnorm),
+        ("trim", Some(range)) => {
+            let (start, end) = range.split_once('-')?;
+            Box::new(Trim {
+                start: start.trim().to_string(),
+                end: end.trim().to_string(),
+            })
+        }
+        _ => return None,
+    };
+    Some(processor)
+}
+
+/// Run `chain` in order on `input`, returning the final file.
+/// `on_stage` is called with the index, the processor and its input before each step.
+/// An output already there follows `on_conflict` in config, the chain stops at a skipped one.
+pub async fn run_chain<S, F>(
+    chain: &[Box<dyn PostProcessor>],
+    input: PathBuf,
+    progress: &AtomicUsize,
+    mut on_stage: S,
+    cancelled: F,
+) -> TempDirResult<PathBuf>
+where
+    S: FnMut(usize, &dyn PostProcessor, &Path),
+    F: Fn() -> bool,
+{
+    let mut input = input;
+    let policy = ConflictPolicy::from_config();
+    for (i, processor) in chain.iter().enumerate() {
+        let ext = processor.ext(&input);
+        let Some(output) = step_output(&input, &ext, policy) else {
+            tracing::info!("{:?} already exists, skipped", input.with_extension(ext));
+            break;
+        };
+        on_stage(i, processor.as_ref(), &input);
+        // ffmpeg can not write in place
+        let working = output.with_extension(format!("part.{ext}"));
+        let args = processor.args(&input, &working);
+        if let Err(e) = ffmpeg::run(args, progress, &cancelled).await {
+            std::fs::remove_file(&working).ok();
+            return Err(e);
+        }
+        if !processor.keep_input() && input != output {
+            std::fs::remove_file(&input)?;
+        }
+        std::fs::rename(&working, &output)?;
+        input = output;
+    }
+    Ok(input)
+}
+
+/// Where a step writes `input` as `ext`, `None` if it is skipped.
+/// The same path replaces the input, which is ours, another file there is the user's
+fn step_output(input: &Path, ext: &str, policy: ConflictPolicy) -> Option<PathBuf> {
+    let output = input.with_extension(ext);
+    match output == input {
+        true => Some(output),
+        false => resolve_conflict(&output, policy),
+    }
+}
+
+fn path_arg(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+fn ext_of(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or("mp4".to_string())
+}
+
+/// Change the container without re-encoding
+#[derive(Debug)]
+pub struct Remux(&'static str);
+
+impl PostProcessor for Remux {
+    fn name(&self) -> String {
+        format!("remux to {}", self.0)
+    }
+
+    fn ext(&self, _input: &Path) -> String {
+        self.0.to_string()
+    }
+
+    fn args(&self, input: &Path, output: &Path) -> Vec<String> {
+        vec![
+            "-i".to_string(),
+            path_arg(input),
+            "-map".to_string(),
+            "0".to_string(),
+            "-c".to_string(),
+            "copy".to_string(),
+            "-y".to_string(),
+            path_arg(output),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Mp3,
+    M4a,
+    Flac,
+}
+
+impl std::str::FromStr for AudioFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mp3" => Ok(Self::Mp3),
+            "m4a" => Ok(Self::M4a),
+            "flac" => Ok(Self::Flac),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AudioFormat {
+    pub fn ext(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Flac => "flac",
+        }
+    }
+
+    pub fn codec_args(&self) -> Vec<String> {
+        let args: &[&str] = match self {
+            AudioFormat::Mp3 => &["-c:a", "libmp3lame", "-q:a", "2"],
+            // bilibili serves aac already
+            AudioFormat::M4a => &["-c:a", "copy"],
+            AudioFormat::Flac => &["-c:a", "flac"],
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+}
+
+/// Keep the audio only, the video is kept as well
+#[derive(Debug)]
+pub struct ExtractAudio(pub AudioFormat);
+
+impl PostProcessor for ExtractAudio {
+    fn name(&self) -> String {
+        format!("extract {}", self.0.ext())
+    }
+
+    fn ext(&self, _input: &Path) -> String {
+        self.0.ext().to_string()
+    }
+
+    fn args(&self, input: &Path, output: &Path) -> Vec<String> {
+        let mut args = vec!["-i".to_string(), path_arg(input), "-vn".to_string()];
+        args.extend(self.0.codec_args());
+        args.extend(["-y".to_string(), path_arg(output)]);
+        args
+    }
+
+    fn keep_input(&self) -> bool {
+        true
+    }
+}
+
+/// Re-encode the video to H.264 for old devices
+#[derive(Debug)]
+pub struct ReencodeH264;
+
+impl PostProcessor for ReencodeH264 {
+    fn name(&self) -> String {
+        "re-encode to h264".to_string()
+    }
+
+    fn ext(&self, input: &Path) -> String {
+        ext_of(input)
+    }
+
+    fn args(&self, input: &Path, output: &Path) -> Vec<String> {
+        [
+            "-i",
+            &path_arg(input),
+            "-c:v",
+            "libx264",
+            "-preset",
+            "medium",
+            "-crf",
+            "20",
+            "-pix_fmt",
+            "yuv420p",
+            "-c:a",
+            "copy",
+            "-y",
+            &path_arg(output),
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+}
+
+/// Cut to `start`-`end`, both in ffmpeg time syntax
+#[derive(Debug)]
+pub struct Trim {
+    start: String,
+    end: String,
+}
+
+impl PostProcessor for Trim {
+    fn name(&self) -> String {
+        format!("trim {}-{}", self.start, self.end)
+    }
+
+    fn ext(&self, input: &Path) -> String {
+        ext_of(input)
+    }
+
+    fn args(&self, input: &Path, output: &Path) -> Vec<String> {
+        [
+            "-i",
+            &path_arg(input),
+            "-ss",
+            &self.start,
+            "-to",
+            &self.end,
+            "-map",
+            "0",
+            "-c",
+            "copy",
+            "-y",
+            &path_arg(output),
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+}
+
+/// Normalize the loudness with the EBU R128 filter
+#[derive(Debug)]
+pub struct Loudnorm;
+
+impl PostProcessor for Loudnorm {
+    fn name(&self) -> String {
+        "normalize loudness".to_string()
+    }
+
+    fn ext(&self, input: &Path) -> String {
+        ext_of(input)
+    }
+
+    fn args(&self, input: &Path, output: &Path) -> Vec<String> {
+        [
+            "-i",
+            &path_arg(input),
+            "-af",
+            "loudnorm",
+            "-c:v",
+            "copy",
+            "-y",
+            &path_arg(output),
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_spec_test() {
+        assert_eq!(from_spec("mkv").unwrap().name(), "remux to mkv");
+        assert_eq!(from_spec("mp3").unwrap().ext(Path::new("a.mp4")), "mp3");
+        assert_eq!(from_spec("h264").unwrap().ext(Path::new("a.mkv")), "mkv");
+        let trim = from_spec("trim:00:00:10-00:01:00").unwrap();
+        assert_eq!(trim.name(), "trim 00:00:10-00:01:00");
+        assert!(trim
+            .args(Path::new("a.mp4"), Path::new("b.mp4"))
+            .windows(2)
+            .any(|w| w == ["-to", "00:01:00"]));
+        assert!(from_spec("trim").is_none());
+        assert!(from_spec("unknown").is_none());
+    }
+
+    #[test]
+    fn step_output_test() {
+        let dir = tempdir::TempDir::new("post_process").unwrap();
+        let input = dir.path().join("a.mp4");
+        let taken = dir.path().join("a.mkv");
+        std::fs::write(&input, b"").unwrap();
+        std::fs::write(&taken, b"").unwrap();
+        assert_eq!(
+            step_output(&input, "mp3", ConflictPolicy::Skip),
+            Some(dir.path().join("a.mp3"))
+        );
+        assert_eq!(
+            step_output(&input, "mp4", ConflictPolicy::Skip),
+            Some(input.clone())
+        );
+        assert_eq!(step_output(&input, "mkv", ConflictPolicy::Skip), None);
+        assert_eq!(
+            step_output(&input, "mkv", ConflictPolicy::Overwrite),
+            Some(taken)
+        );
+        let numbered = step_output(&input, "mkv", ConflictPolicy::AutoNumber).unwrap();
+        assert!(!numbered.exists());
+        assert_eq!(numbered.extension().unwrap(), "mkv");
+    }
+}
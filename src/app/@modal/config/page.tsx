@@ -1,3 +1,5 @@
+import BiliLogin from "@/components/bili-login";
+import ConfigBackup from "@/components/config-backup";
 import ConfigForm from "@/components/config-form";
 import Modal from "@/components/modal-c";
 
@@ -5,6 +7,8 @@ export default function configModal() {
     return (
         <Modal>
             <ConfigForm />
+            <BiliLogin />
+            <ConfigBackup />
         </Modal>
     )
-}
\ No newline at end of file
+}
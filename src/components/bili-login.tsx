@@ -0,0 +1,144 @@
+'use client'
+
+import { invoke } from "@tauri-apps/api/tauri";
+import { QRCodeSVG } from "qrcode.react";
+import { useEffect, useState } from "react"
+import Input from "./input-c"
+
+interface QrCode { url: string, key: string }
+
+interface Account {
+	logged_in: boolean,
+	name?: string,
+	level?: number,
+	vip: boolean,
+	vip_expires?: number,
+	cookie_expires?: number,
+}
+
+function date(seconds?: number) {
+	return seconds ? new Date(seconds * 1000).toLocaleDateString() : "unknown"
+}
+
+interface QrStatus { status: "waiting" | "scanned" | "expired" | "done", profile?: string }
+
+export default function BiliLogin() {
+	let [profile, setProfile] = useState("")
+	let [qrcode, setQrcode] = useState<QrCode | null>(null)
+	let [message, setMessage] = useState("")
+	let [path, setPath] = useState("")
+
+	useEffect(() => {
+		if (!qrcode) return
+		let ignore = false
+		const poll = async () => {
+			try {
+				let status = await invoke("bili_login_poll", { key: qrcode.key, profile: profile || null }) as QrStatus
+				if (ignore) return
+				if (status.status === "done") {
+					setMessage(`Logged in, cookies saved to ${status.profile}`)
+					setQrcode(null)
+				} else if (status.status === "expired") {
+					setMessage("QR code expired")
+					setQrcode(null)
+				} else {
+					setMessage(status.status === "scanned" ? "Confirm in the bilibili app" : "Scan with the bilibili app")
+				}
+			} catch (e) {
+				if (!ignore) {
+					setMessage(String(e))
+					setQrcode(null)
+				}
+			}
+		}
+		let timer = setInterval(poll, 2000)
+		async function checkAccount() {
+		try {
+			let account = await invoke("bili_account", { profile: profile || null }) as Account
+			setMessage(account.logged_in
+				? `${account.name} (lv${account.level})${account.vip ? `, VIP until ${date(account.vip_expires)}` : ""}, cookie expires ${date(account.cookie_expires)}`
+				: "Not logged in, the cookie may have expired")
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	async function importCookies() {
+		try {
+			let count = await invoke("import_cookies", { site: "bili", profile: profile || null, path }) as number
+			setMessage(`Imported ${count} cookies`)
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	async function exportCookies() {
+		try {
+			await invoke("export_cookies", { site: "bili", profile: profile || null, path })
+			setMessage(`Exported to ${path}`)
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	const button = "rounded-md bg-indigo-500 mx-1 w-fit px-3.5 py-2 text-sm font-semibold text-white shadow-sm hover:bg-indigo-400"
+	return () => { ignore = true; clearInterval(timer) }
+	}, [qrcode, profile])
+
+	async function login() {
+		try {
+			setQrcode(await invoke("bili_login_qrcode") as QrCode)
+			setMessage("Scan with the bilibili app")
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	async function checkAccount() {
+		try {
+			let account = await invoke("bili_account", { profile: profile || null }) as Account
+			setMessage(account.logged_in
+				? `${account.name} (lv${account.level})${account.vip ? `, VIP until ${date(account.vip_expires)}` : ""}, cookie expires ${date(account.cookie_expires)}`
+				: "Not logged in, the cookie may have expired")
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	async function importCookies() {
+		try {
+			let count = await invoke("import_cookies", { site: "bili", profile: profile || null, path }) as number
+			setMessage(`Imported ${count} cookies`)
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	async function exportCookies() {
+		try {
+			await invoke("export_cookies", { site: "bili", profile: profile || null, path })
+			setMessage(`Exported to ${path}`)
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	const button = "rounded-md bg-indigo-500 mx-1 w-fit px-3.5 py-2 text-sm font-semibold text-white shadow-sm hover:bg-indigo-400"
+	return (
+		<div className="flex flex-col place-items-center mt-4">
+			<div className="flex flex-row place-items-center">
+				<Input name="profile" value={profile} required={false} placeholder="default"
+					onChange={(e) => setProfile(e.target.value)} />
+				<button type="button" className={button} onClick={login}> Login bilibili </button>
+				<button type="button" className={button} onClick={checkAccount}> Check </button>
+			</div>
+			<div className="flex flex-row mt-2 place-items-center">
+				<Input name="cookies.txt path" value={path} required={false} onChange={(e) => setPath(e.target.value)} />
+				<button type="button" className={button} onClick={importCookies}> Import </button>
+				<button type="button" className={button} onClick={exportCookies}> Export </button>
+			</div>
+			{qrcode && <QRCodeSVG value={qrcode.url} size={160} includeMargin={true} className="mt-2" />}
+			{message && <p className="mt-2 text-sm"> {message} </p>}
+		</div>
+	)
+}
@@ -0,0 +1,51 @@
+'use client'
+
+import { invoke } from "@tauri-apps/api/tauri";
+import { useState } from "react"
+import Input from "./input-c"
+
+interface FieldError { key: string, message: string }
+
+export default function ConfigBackup() {
+	let [path, setPath] = useState("")
+	let [passphrase, setPassphrase] = useState("")
+	let [includeSecrets, setIncludeSecrets] = useState(true)
+	let [message, setMessage] = useState("")
+
+	async function exportConfig() {
+		try {
+			await invoke("export_config", { path, passphrase, includeSecrets })
+			setMessage(`Exported to ${path}`)
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	async function importConfig() {
+		try {
+			let skipped = await invoke("import_config", { path, passphrase }) as FieldError[]
+			setMessage(skipped.length === 0 ? "Imported"
+				: `Imported, skipped ${skipped.map((e) => `${e.key} ${e.message}`).join(", ")}`)
+		} catch (e) {
+			setMessage(String(e))
+		}
+	}
+
+	const button = "rounded-md bg-indigo-500 mx-1 w-fit px-3.5 py-2 text-sm font-semibold text-white shadow-sm hover:bg-indigo-400"
+	return (
+		<div className="flex flex-col place-items-center mt-4">
+			<div className="flex flex-row">
+				<Input name="bundle path" value={path} required={false} onChange={(e) => setPath(e.target.value)} />
+				<Input name="passphrase" type="password" value={passphrase} required={false} onChange={(e) => setPassphrase(e.target.value)} />
+			</div>
+			<div className="flex flex-row mt-2 place-items-center">
+				<label className="mx-2 text-sm">
+					<input type="checkbox" checked={includeSecrets} onChange={(e) => setIncludeSecrets(e.target.checked)} /> include cookies
+				</label>
+				<button type="button" className={button} onClick={exportConfig}> Export </button>
+				<button type="button" className={button} onClick={importConfig}> Import </button>
+			</div>
+			{message && <p className="mt-2 text-sm"> {message} </p>}
+		</div>
+	)
+}
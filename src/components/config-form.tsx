@@ -6,15 +6,55 @@ import Input from "./input-c"
 
 interface ConfigForm { [key: string]: string }
 
+interface Diagnostics { path?: string, version?: string, problems: string[] }
+
+interface FieldSchema {
+	key: string,
+	description: string,
+	type: string,
+	default: string,
+	profiled: boolean,
+	min?: number,
+	max?: number,
+	options?: string[],
+}
+
+interface FieldError { key: string, message: string }
+
+function Field(props: { field: FieldSchema, value: string }) {
+	const { field, value } = props
+	if (field.type === "choice" || field.type === "bool") {
+		let options = field.options ?? ["true", "false"]
+		return (
+			<select name={field.key} defaultValue={value} title={field.description}
+				className="min-w-0 rounded-md border-0 bg-white/5 px-3.5 py-2 mx-1 shadow-sm ring-1 ring-inset ring-blue sm:text-sm sm:leading-6">
+				{options.map((option) => <option key={option} value={option}> {option || "default"} </option>)}
+			</select>
+		)
+	}
+	let type = field.type === "number" ? "number" : field.type === "cookie" ? "password" : "text"
+	return (
+		<Input name={field.key} defaultValue={value} required={false} type={type}
+			min={field.min} max={field.max} title={field.description} placeholder={field.default} />
+	)
+}
+
 export default function ConfigForm() {
 	let [config, setConfig] = useState<ConfigForm>({})
+	let [schema, setSchema] = useState<FieldSchema[]>([])
+	let [diagnostics, setDiagnostics] = useState<Diagnostics>({ problems: [] })
+	let [errors, setErrors] = useState<FieldError[]>([])
 
 	useEffect(() => {
 		let ignore = false
 		const init = async () => {
 			let new_config = await invoke("show_config") as ConfigForm
+			let new_schema = await invoke("config_schema") as FieldSchema[]
+			let new_diagnostics = await invoke("diagnostics") as Diagnostics
 			if (!ignore) {
 				setConfig(new_config)
+				setSchema(new_schema)
+				setDiagnostics(new_diagnostics)
 			}
 		}
 		init()
@@ -24,7 +64,14 @@ export default function ConfigForm() {
 	async function upgrade(e: FormEvent<HTMLFormElement>) {
 		let form = new FormData(e.currentTarget)
 		let json = Object.fromEntries(form)
-		await invoke("upgrade_config", { json })
+		try {
+			await invoke("upgrade_config", { json })
+		} catch (e) {
+			setErrors(e as FieldError[])
+			return
+		}
+		setErrors([])
+		setDiagnostics(await invoke("diagnostics") as Diagnostics)
 		let new_config: ConfigForm = {}
 		for (const key in json) {
 			new_config[key] = json[key].toString()
@@ -32,25 +79,40 @@ export default function ConfigForm() {
 		setConfig(new_config)
 	}
 
+	// keys without a schema, e.g. cookie profiles, are edited as text
+	let fields = [
+		...schema,
+		...Object.keys(config)
+			.filter((key) => !schema.some((field) => field.key === key))
+			.map((key) => ({ key, description: key, type: "text", default: "", profiled: false })),
+	]
+
 	return (
 		<>
 			<form className="flex flex-col place-items-center overflow-y-auto" onSubmit={(e) => { e.preventDefault(); upgrade(e); }}>
-				{Object.keys(config).map((key) => {
+				{[...errors.filter((e) => !e.key).map((e) => e.message), ...diagnostics.problems].map((problem) => {
+					return (
+						<p key={problem} className="mt-2 text-sm text-red-500"> {problem} </p>
+					)
+				})}
+				{fields.map((field) => {
+					let error = errors.find((e) => e.key === field.key)
 					return (
-						<div key={key} className="grid grid-cols-3 mt-2 place-content-center">
-							<label className="col-start-1 col-span-1 place-items-center mx-2 py-2"> {key} </label>
+						<div key={field.key} className="grid grid-cols-3 mt-2 place-content-center">
+							<label className="col-start-1 col-span-1 place-items-center mx-2 py-2" title={field.description}> {field.key} </label>
 							<div className="col-start-2 col-span-2">
-								<Input name={key} defaultValue={config[key]} required={false} />
+								<Field field={field} value={config[field.key] ?? field.default} />
+								{error && <p className="mx-1 text-sm text-red-500"> {error.message} </p>}
 							</div>
 						</div>
 					)
 				})}
 				<button type="submit"
-					className="rounded-md bg-indigo-500 mt-4 w-fit px-3.5 py-2 text-sm font-semibold text-white shadow-sm 
-                    hover:bg-indigo-400 
+					className="rounded-md bg-indigo-500 mt-4 w-fit px-3.5 py-2 text-sm font-semibold text-white shadow-sm
+                    hover:bg-indigo-400
                     focus-visible:outline focus-visible:outline-2 focus-visible:outline-offset-2 focus-visible:outline-indigo-500"
 				> Upgrade Config </button>
 			</form>
 		</>
 	)
-}
\ No newline at end of file
+}
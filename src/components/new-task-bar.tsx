@@ -1,28 +1,51 @@
 'use client'
-import { FormEvent } from "react";
+import { FormEvent, useEffect, useState } from "react";
 import Input from "./input-c";
 import { invoke } from "@tauri-apps/api/tauri";
 import { useRouter } from "next/navigation";
 
 export default function NewTaskBar() {
     const router = useRouter()
+    let [profiles, setProfiles] = useState<string[]>([])
+    let [message, setMessage] = useState("")
+
+    useEffect(() => {
+        let ignore = false
+        invoke("list_profiles", { site: "bili" }).then((p) => { if (!ignore) setProfiles(p as string[]) })
+        return () => { ignore = true }
+    }, [])
+
     const onsubmit = async (e: FormEvent<HTMLFormElement>) => {
         e.preventDefault()
         let form = new FormData(e.currentTarget)
         let url = form.get('url')?.toString()
-        let ret = await invoke("create", { url })
-        console.log(ret)
-        router.push("/taskList")
+        // empty uses the profile chosen in config
+        let cookie_profile = form.get('profile')?.toString() || null
+        try {
+            let ret = await invoke("create", { url, options: { cookie_profile } })
+            console.log(ret)
+            setMessage("")
+            router.push("/taskList")
+        } catch (e) {
+            setMessage(String(e))
+        }
     }
     return (
         <>
             <form onSubmit={(e) => onsubmit(e)} className="flex">
                 <label htmlFor="url"></label>
                 <Input name="url" type="url" required={true} />
+                {profiles.length > 1 &&
+                    <select name="profile" defaultValue="" title="Cookie profile"
+                        className="min-w-0 rounded-md border-0 bg-white/5 px-3.5 py-2 ml-4 shadow-sm ring-1 ring-inset ring-blue sm:text-sm sm:leading-6">
+                        <option value=""> config </option>
+                        {profiles.map((profile) => <option key={profile} value={profile}> {profile} </option>)}
+                    </select>}
                 <button type="submit"
                     className="rounded-md bg-indigo-500 px-3.5 py-2. ml-4 text-sm font-semibold text-white shadow-sm hover:bg-indigo-400 focus-visible:outline focus-visible:outline-2 focus-visible:outline-offset-2 focus-visible:outline-indigo-500"
                 > Go </button>
             </form>
+            {message && <p className="mt-2 text-sm text-red-500"> {message} </p>}
         </>
     )
 }
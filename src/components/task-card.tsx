@@ -4,8 +4,8 @@ import { stat } from "fs"
 import BtnInvoke from "./btn-invoke"
 import ProgressBar from "./progress"
 
-// title finished total uuid state
-export type CardInfo = [string, number, number, string, string]
+// title finished total uuid state warning
+export type CardInfo = [string, number, number, string, string, string]
 
 export default function TaskCard({ info }: { info: CardInfo }) {
     // `>>20` to Mb then `<<1 + <<3` to x10
@@ -18,6 +18,7 @@ export default function TaskCard({ info }: { info: CardInfo }) {
             <h1 className="text-xl font-bold mb-2">{info[0]}</h1>
             <div className="text-gray-700">{`${finished}/${total} Mb`}</div>
             <ProgressBar progress={progress} state={info[4]} />
+            {info[5] && <div className="text-sm text-yellow-600 pt-2">{info[5]}</div>}
             <div className="btns pt-4 flex justify-center">
                 {buttons(info[4], info[3])}
             </div>
@@ -38,7 +39,7 @@ function buttons(state: string, id: string) {
                 state == "paused" && <BtnInvoke func="continue_" params={{ id }} desc="Continue" />
             }
             {
-                (state == "cancelled" || state == "finished") || <BtnInvoke func="cancel" params={{ id }} desc="Cancel" />
+                (state == "cancelled" || state == "finished" || state == "failed") || <BtnInvoke func="cancel" params={{ id }} desc="Cancel" />
             }
             <BtnInvoke func="remove" params={{ id }} desc="Remove" />
         </>)